@@ -0,0 +1,161 @@
+//! Conversion of CMYK data to RGB
+
+use crate::{
+    icc, ColorConversion, DecoderError, Frame, GenericContexts, MemoryFormat, SharedMemory,
+};
+use rgb::{AsPixels, RGB, RGBA};
+use safe_transmute::error::Error as TsmErr;
+
+/// Profiles used for images without an embedded CMYK profile
+///
+/// The first profile that exists is used.
+const DEFAULT_PROFILES: &[&str] = &[
+    "/usr/share/color/icc/colord/FOGRA39L_coated.icc",
+    "/usr/share/color/icc/colord/SWOP_coated_20_16.icc",
+    "/usr/share/color/icc/ghostscript/default_cmyk.icc",
+    "/usr/share/ghostscript/iccprofiles/default_cmyk.icc",
+];
+
+/// Interleaved CMYK values where higher values mean more ink
+pub enum CmykData {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+}
+
+impl CmykData {
+    fn len(&self) -> usize {
+        match self {
+            Self::U8(data) => data.len(),
+            Self::U16(data) => data.len(),
+        }
+    }
+}
+
+/// Creates a frame in the target profile of the color conversion from CMYK data
+///
+/// The embedded ICC profile is used if it is a CMYK profile. Otherwise a
+/// default profile like FOGRA39 or SWOP is used, if installed.
+pub fn cmyk_frame(
+    width: u32,
+    height: u32,
+    data: CmykData,
+    iccp: Option<&[u8]>,
+    color_conversion: &ColorConversion,
+) -> Result<Frame, DecoderError> {
+    let n_pixels = usize::try_from(u64::from(width) * u64::from(height)).context_internal()?;
+    if data.len() != n_pixels * 4 {
+        return Err(DecoderError::DecodingError(String::from(
+            "CMYK data does not match image dimensions",
+        )));
+    }
+
+    let memory_format = match data {
+        CmykData::U8(_) => MemoryFormat::R8g8b8,
+        CmykData::U16(_) => MemoryFormat::R16g16b16,
+    };
+
+    let mut memory = SharedMemory::new(n_pixels as u64 * memory_format.n_bytes().u64());
+
+    let profile = iccp
+        .filter(|iccp| is_cmyk_profile(iccp))
+        .map(<[u8]>::to_vec)
+        .or_else(default_profile);
+
+    match data {
+        CmykData::U8(data) => {
+            let cmyk: &[RGBA<u8>] = data.as_pixels();
+            let rgb: &mut [RGB<u8>] = memory.as_pixels_mut();
+
+            if let Some(profile) = &profile {
+                icc::transform_cmyk(
+                    profile,
+                    (cmyk, lcms2::PixelFormat::CMYK_8),
+                    (rgb, lcms2::PixelFormat::RGB_8),
+                    color_conversion,
+                )
+                .context_failed()?;
+            } else {
+                naive_to_rgb(cmyk, rgb, u8::MAX);
+            }
+        }
+        CmykData::U16(data) => {
+            let cmyk: &[RGBA<u16>] = data.as_pixels();
+            let buf: &mut [u16] = safe_transmute::transmute_many_pedantic_mut(&mut memory)
+                .map_err(TsmErr::without_src)
+                .context_internal()?;
+            let rgb: &mut [RGB<u16>] = buf.as_pixels_mut();
+
+            if let Some(profile) = &profile {
+                icc::transform_cmyk(
+                    profile,
+                    (cmyk, lcms2::PixelFormat::CMYK_16),
+                    (rgb, lcms2::PixelFormat::RGB_16),
+                    color_conversion,
+                )
+                .context_failed()?;
+            } else {
+                naive_to_rgb(cmyk, rgb, u16::MAX);
+            }
+        }
+    }
+
+    let mut frame = Frame::new(width, height, memory_format, memory.into_texture());
+    // Marks the frame as already converted, naive conversions are treated as sRGB
+    if profile.is_some() {
+        frame.iccp = icc::target_icc(color_conversion).ok().into();
+    }
+
+    Ok(frame)
+}
+
+fn is_cmyk_profile(iccp: &[u8]) -> bool {
+    lcms2::Profile::new_icc(iccp)
+        .is_ok_and(|profile| profile.color_space() == lcms2::ColorSpaceSignature::CmykData)
+}
+
+fn default_profile() -> Option<Vec<u8>> {
+    DEFAULT_PROFILES
+        .iter()
+        .filter_map(|path| std::fs::read(path).ok())
+        .find(|iccp| is_cmyk_profile(iccp))
+}
+
+/// Conversion without color management, used if no CMYK profile is available
+fn naive_to_rgb<T>(cmyk: &[RGBA<T>], rgb: &mut [RGB<T>], max: T)
+where
+    T: Copy + Into<u32> + TryFrom<u32>,
+{
+    let max_u32: u32 = max.into();
+    let channel = |x: T, k: T| -> T {
+        let value = (max_u32 - x.into()) * (max_u32 - k.into()) / max_u32;
+        T::try_from(value).unwrap_or(max)
+    };
+
+    for (cmyk, rgb) in cmyk.iter().zip(rgb.iter_mut()) {
+        *rgb = RGB::new(
+            channel(cmyk.r, cmyk.a),
+            channel(cmyk.g, cmyk.a),
+            channel(cmyk.b, cmyk.a),
+        );
+    }
+}
+
+#[test]
+fn naive_to_rgb_test() {
+    let cmyk = [
+        RGBA::new(0u8, 0, 0, 0),
+        RGBA::new(255, 0, 0, 0),
+        RGBA::new(0, 0, 0, 255),
+    ];
+    let mut rgb = [RGB::new(0, 0, 0); 3];
+    naive_to_rgb(&cmyk, &mut rgb, u8::MAX);
+
+    assert_eq!(
+        rgb,
+        [
+            RGB::new(255, 255, 255),
+            RGB::new(0, 255, 255),
+            RGB::new(0, 0, 0)
+        ]
+    );
+}
@@ -0,0 +1,168 @@
+//! Parsing of EXIF data into [`ExifInfo`]
+
+use crate::ExifInfo;
+use exif::{Field, In, Rational, Tag, Value};
+
+impl ExifInfo {
+    /// Parses EXIF data in TIFF structure
+    pub fn parse(exif: &[u8]) -> Option<Self> {
+        let data = exif.strip_prefix(b"Exif\0\0").unwrap_or(exif);
+        let exif = exif::Reader::new().read_raw(data.to_vec()).ok()?;
+        let field = |tag| exif.get_field(tag, In::PRIMARY);
+
+        let gps_position =
+            gps_coordinate(field(Tag::GPSLatitude), field(Tag::GPSLatitudeRef), b'S').zip(
+                gps_coordinate(field(Tag::GPSLongitude), field(Tag::GPSLongitudeRef), b'W'),
+            );
+
+        let gps_altitude = field(Tag::GPSAltitude).and_then(rational).map(|altitude| {
+            // Reference 1 means below sea level
+            if field(Tag::GPSAltitudeRef).and_then(|x| x.value.get_uint(0)) == Some(1) {
+                -altitude.to_f64()
+            } else {
+                altitude.to_f64()
+            }
+        });
+
+        // Only inch and centimeter are valid units
+        let resolution = match field(Tag::ResolutionUnit).and_then(|x| x.value.get_uint(0)) {
+            None | Some(2) => Some(1.),
+            Some(3) => Some(2.54),
+            _ => None,
+        }
+        .and_then(|factor| {
+            let x = field(Tag::XResolution).and_then(rational)?.to_f64();
+            let y = field(Tag::YResolution).and_then(rational)?.to_f64();
+            Some((x * factor, y * factor))
+        });
+
+        Some(Self {
+            date_time_original: date_time(
+                field(Tag::DateTimeOriginal),
+                field(Tag::OffsetTimeOriginal),
+            )
+            .into(),
+            image_description: field(Tag::ImageDescription).and_then(ascii).into(),
+            make: field(Tag::Make).and_then(ascii).into(),
+            model: field(Tag::Model).and_then(ascii).into(),
+            lens_make: field(Tag::LensMake).and_then(ascii).into(),
+            lens_model: field(Tag::LensModel).and_then(ascii).into(),
+            exposure_time: field(Tag::ExposureTime)
+                .and_then(rational)
+                .map(|x| (x.num, x.denom))
+                .into(),
+            f_number: field(Tag::FNumber)
+                .and_then(rational)
+                .map(|x| x.to_f64())
+                .into(),
+            iso: field(Tag::PhotographicSensitivity)
+                .and_then(|x| x.value.get_uint(0))
+                .into(),
+            focal_length: field(Tag::FocalLength)
+                .and_then(rational)
+                .map(|x| x.to_f64())
+                .into(),
+            gps_position: gps_position.into(),
+            gps_altitude: gps_altitude.into(),
+            orientation: field(Tag::Orientation)
+                .and_then(|x| x.value.get_uint(0))
+                .and_then(|x| u16::try_from(x).ok())
+                .into(),
+            resolution: resolution.into(),
+        })
+    }
+}
+
+fn ascii(field: &Field) -> Option<String> {
+    let Value::Ascii(values) = &field.value else {
+        return None;
+    };
+
+    let value = String::from_utf8_lossy(values.first()?).trim().to_string();
+    Some(value).filter(|x| !x.is_empty())
+}
+
+fn rational(field: &Field) -> Option<Rational> {
+    let Value::Rational(values) = &field.value else {
+        return None;
+    };
+
+    values.first().copied().filter(|x| x.denom != 0)
+}
+
+/// Formats the date as ISO 8601, with time zone if known
+fn date_time(date_time: Option<&Field>, offset: Option<&Field>) -> Option<String> {
+    let Value::Ascii(values) = &date_time?.value else {
+        return None;
+    };
+    let mut date_time = exif::DateTime::from_ascii(values.first()?).ok()?;
+
+    if let Some(Value::Ascii(values)) = offset.map(|x| &x.value) {
+        if let Some(value) = values.first() {
+            let _ = date_time.parse_offset(value);
+        }
+    }
+
+    let mut formatted = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        date_time.year,
+        date_time.month,
+        date_time.day,
+        date_time.hour,
+        date_time.minute,
+        date_time.second
+    );
+
+    if let Some(offset) = date_time.offset {
+        let sign = if offset < 0 { '-' } else { '+' };
+        let offset = offset.unsigned_abs();
+        formatted.push_str(&format!("{sign}{:02}:{:02}", offset / 60, offset % 60));
+    }
+
+    Some(formatted)
+}
+
+/// Degrees from degrees, minutes, and seconds
+fn gps_coordinate(value: Option<&Field>, reference: Option<&Field>, negative: u8) -> Option<f64> {
+    let Value::Rational(values) = &value?.value else {
+        return None;
+    };
+
+    let [degrees, minutes, seconds] = values.get(..3)? else {
+        return None;
+    };
+    if [degrees, minutes, seconds].iter().any(|x| x.denom == 0) {
+        return None;
+    }
+
+    let coordinate = degrees.to_f64() + minutes.to_f64() / 60. + seconds.to_f64() / 3600.;
+
+    let is_negative = matches!(
+        reference.map(|x| &x.value),
+        Some(Value::Ascii(values)) if values.first().and_then(|x| x.first()) == Some(&negative)
+    );
+
+    Some(if is_negative { -coordinate } else { coordinate })
+}
+
+#[test]
+fn exif_info_test() {
+    let mut exif = b"MM\0*".to_vec();
+    exif.extend(8_u32.to_be_bytes());
+    exif.extend(2_u16.to_be_bytes());
+    // Make, ASCII with value stored after the IFD
+    exif.extend([0x01, 0x0F, 0, 2]);
+    exif.extend(6_u32.to_be_bytes());
+    exif.extend(38_u32.to_be_bytes());
+    // Orientation, SHORT
+    exif.extend([0x01, 0x12, 0, 3]);
+    exif.extend(1_u32.to_be_bytes());
+    exif.extend([0, 6, 0, 0]);
+    // No next IFD
+    exif.extend(0_u32.to_be_bytes());
+    exif.extend(b"Canon\0");
+
+    let exif_info = ExifInfo::parse(&exif).unwrap();
+    assert_eq!(exif_info.make.as_deref(), Some("Canon"));
+    assert_eq!(exif_info.orientation.as_ref(), Some(&6));
+}
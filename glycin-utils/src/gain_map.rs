@@ -0,0 +1,467 @@
+//! HDR reconstruction from gain maps
+//!
+//! Ultra HDR XMP and Apple's HDR gain maps are expressed with the parameters
+//! of ISO 21496-1.
+
+use crate::tone_mapping::{hlg_to_linear, pq_to_linear, srgb_to_linear, PixelLayout};
+use crate::xml::xmp_property;
+use crate::{
+    Cicp, ColorPrimaries, Frame, GainMapInfo, MemoryFormat, SharedMemory, Texture,
+    TransferCharacteristics,
+};
+use exif::{In, Tag, Value};
+
+use std::os::fd::AsRawFd;
+
+/// Apple maker note tags used to compute the headroom
+const APPLE_TAG_HDR_HEADROOM: u16 = 33;
+const APPLE_TAG_HDR_GAIN: u16 = 48;
+
+impl GainMapInfo {
+    /// Parses Ultra HDR metadata from the XMP of the gain map image
+    pub fn from_xmp(xmp: &[u8]) -> Option<Self> {
+        let xmp = std::str::from_utf8(xmp).ok()?;
+
+        let values = |name: &str, default: Option<f64>| {
+            let values = xmp_property(xmp, &format!("hdrgm:{name}"))
+                .iter()
+                .map(|x| x.parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()
+                .ok()?;
+            match values.as_slice() {
+                [] => default.map(|x| (x, x, x)),
+                [x] => Some((*x, *x, *x)),
+                [r, g, b] => Some((*r, *g, *b)),
+                _ => None,
+            }
+        };
+        let value = |name, default| values(name, default).map(|x| x.0);
+
+        let hdr_capacity_min = value("HDRCapacityMin", Some(0.))?;
+        let hdr_capacity_max = value("HDRCapacityMax", None)?;
+        let base_rendition_is_hdr = xmp_property(xmp, "hdrgm:BaseRenditionIsHDR")
+            .first()
+            .is_some_and(|x| x.eq_ignore_ascii_case("true"));
+
+        let offset_sdr = values("OffsetSDR", Some(1. / 64.))?;
+        let offset_hdr = values("OffsetHDR", Some(1. / 64.))?;
+
+        let (base_headroom, alternate_headroom, base_offset, alternate_offset) =
+            if base_rendition_is_hdr {
+                (hdr_capacity_max, hdr_capacity_min, offset_hdr, offset_sdr)
+            } else {
+                (hdr_capacity_min, hdr_capacity_max, offset_sdr, offset_hdr)
+            };
+
+        Some(Self {
+            gain_map_min: values("GainMapMin", Some(0.))?,
+            gain_map_max: values("GainMapMax", None)?,
+            gamma: values("Gamma", Some(1.))?,
+            base_offset,
+            alternate_offset,
+            base_headroom,
+            alternate_headroom,
+            linear_gain: false,
+        })
+    }
+
+    /// Parses the binary metadata defined by ISO 21496-1
+    pub fn from_iso_21496(data: &[u8]) -> Option<Self> {
+        let mut reader = Reader(data);
+
+        let minimum_version = reader.u16()?;
+        let _writer_version = reader.u16()?;
+        if minimum_version != 0 {
+            return None;
+        }
+
+        let flags = reader.u8()?;
+        let channels = if flags & 0x80 != 0 { 3 } else { 1 };
+        let common_denominator = flags & 0x08 != 0;
+
+        let (base_headroom, alternate_headroom, parameters) = if common_denominator {
+            let d = reader.u32()?;
+            let base_headroom = fraction(reader.u32()?, d)?;
+            let alternate_headroom = fraction(reader.u32()?, d)?;
+            let parameters = (0..channels)
+                .map(|_| {
+                    Some([
+                        fraction(reader.i32()?, d)?,
+                        fraction(reader.i32()?, d)?,
+                        fraction(reader.u32()?, d)?,
+                        fraction(reader.i32()?, d)?,
+                        fraction(reader.i32()?, d)?,
+                    ])
+                })
+                .collect::<Option<Vec<_>>>()?;
+            (base_headroom, alternate_headroom, parameters)
+        } else {
+            let base_headroom = fraction(reader.u32()?, reader.u32()?)?;
+            let alternate_headroom = fraction(reader.u32()?, reader.u32()?)?;
+            let parameters = (0..channels)
+                .map(|_| {
+                    Some([
+                        fraction(reader.i32()?, reader.u32()?)?,
+                        fraction(reader.i32()?, reader.u32()?)?,
+                        fraction(reader.u32()?, reader.u32()?)?,
+                        fraction(reader.i32()?, reader.u32()?)?,
+                        fraction(reader.i32()?, reader.u32()?)?,
+                    ])
+                })
+                .collect::<Option<Vec<_>>>()?;
+            (base_headroom, alternate_headroom, parameters)
+        };
+
+        let per_channel = |i: usize| {
+            let value = |channel: usize| parameters[channel.min(parameters.len() - 1)][i];
+            (value(0), value(1), value(2))
+        };
+
+        Some(Self {
+            gain_map_min: per_channel(0),
+            gain_map_max: per_channel(1),
+            gamma: per_channel(2),
+            base_offset: per_channel(3),
+            alternate_offset: per_channel(4),
+            base_headroom,
+            alternate_headroom,
+            linear_gain: false,
+        })
+    }
+
+    /// Parameters for Apple's HDR gain maps
+    ///
+    /// The headroom is taken from the gain map's XMP or computed from the maker
+    /// notes in the EXIF data of the main image.
+    pub fn from_apple(exif: Option<&[u8]>, gain_map_xmp: Option<&[u8]>) -> Option<Self> {
+        let headroom = gain_map_xmp
+            .and_then(|xmp| std::str::from_utf8(xmp).ok())
+            .and_then(|xmp| {
+                xmp_property(xmp, "HDRGainMap:HDRGainMapHeadroom")
+                    .first()?
+                    .parse::<f64>()
+                    .ok()
+            })
+            .or_else(|| apple_maker_note_headroom(exif?))
+            .filter(|x| x.is_finite() && *x > 1.)?;
+
+        let max = headroom.log2();
+
+        Some(Self {
+            gain_map_min: (0., 0., 0.),
+            gain_map_max: (max, max, max),
+            gamma: (1., 1., 1.),
+            base_offset: (0., 0., 0.),
+            alternate_offset: (0., 0., 0.),
+            base_headroom: 0.,
+            alternate_headroom: max,
+            linear_gain: true,
+        })
+    }
+
+    /// Weight of the gain map for a display with the given log2 headroom
+    fn weight(&self, headroom: f64) -> f32 {
+        let range = self.alternate_headroom - self.base_headroom;
+        if range.abs() < f64::EPSILON {
+            return if headroom >= self.alternate_headroom {
+                1.
+            } else {
+                0.
+            };
+        }
+
+        ((headroom - self.base_headroom) / range).clamp(0., 1.) as f32
+    }
+}
+
+/// Headroom as defined in Apple's documentation on applying the HDR effect
+fn apple_maker_note_headroom(exif: &[u8]) -> Option<f64> {
+    let data = exif.strip_prefix(b"Exif\0\0").unwrap_or(exif);
+    let exif = exif::Reader::new().read_raw(data.to_vec()).ok()?;
+    let maker_note = match &exif.get_field(Tag::MakerNote, In::PRIMARY)?.value {
+        Value::Undefined(data, _) => data.clone(),
+        _ => return None,
+    };
+
+    // Offsets are relative to the start of the maker note
+    let ifd = maker_note.strip_prefix(b"Apple iOS\0")?.get(4..)?;
+    let mut reader = Reader(ifd);
+    let n_entries = reader.u16()?;
+
+    let mut headroom = None;
+    let mut gain = None;
+    for _ in 0..n_entries {
+        let tag = reader.u16()?;
+        let field_type = reader.u16()?;
+        let _count = reader.u32()?;
+        let offset = usize::try_from(reader.u32()?).ok()?;
+
+        // Rational or signed rational
+        if !matches!(field_type, 5 | 10) {
+            continue;
+        }
+        let mut value = Reader(maker_note.get(offset..)?);
+        let value = if field_type == 5 {
+            fraction(value.u32()?, value.u32()?)
+        } else {
+            fraction(value.i32()?, value.i32()?)
+        };
+
+        match tag {
+            APPLE_TAG_HDR_HEADROOM => headroom = value,
+            APPLE_TAG_HDR_GAIN => gain = value,
+            _ => {}
+        }
+    }
+
+    let (headroom, gain) = (headroom?, gain?);
+    let stops = match (headroom < 1., gain <= 0.01) {
+        (true, true) => -20. * gain + 1.8,
+        (true, false) => -0.101 * gain + 1.601,
+        (false, true) => -70. * gain + 3.,
+        (false, false) => -0.303 * gain + 2.303,
+    };
+
+    Some(f64::powf(2., stops.max(0.)))
+}
+
+fn fraction(n: impl Into<f64>, d: impl Into<f64>) -> Option<f64> {
+    let d = d.into();
+    (d != 0.).then(|| n.into() / d)
+}
+
+/// Big-endian reader
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.0.get(..N)?.try_into().ok()?;
+        self.0 = &self.0[N..];
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes().map(u8::from_be_bytes)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes().map(u16::from_be_bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes().map(u32::from_be_bytes)
+    }
+
+    fn i32(&mut self) -> Option<i32> {
+        self.bytes().map(i32::from_be_bytes)
+    }
+}
+
+/// Applies the gain map to the base image for a display with the given log2 headroom
+///
+/// The base image has to be sRGB or signal its transfer via CICP. `region` is
+/// the part of the whole image covered by the base frame, as relative x, y,
+/// width, and height. The result is linear with the primaries of the base
+/// image, stored as floats.
+pub(crate) fn apply_gain_map(
+    base: &Frame,
+    gain_map: &Frame,
+    info: &GainMapInfo,
+    headroom: f32,
+    region: [f32; 4],
+) -> anyhow::Result<Frame> {
+    let to_linear: fn(f32) -> f32 = match base.cicp.as_ref().map(Cicp::transfer) {
+        Some(TransferCharacteristics::Linear) => |x| x,
+        Some(TransferCharacteristics::Pq) => pq_to_linear,
+        Some(TransferCharacteristics::Hlg) => hlg_to_linear,
+        _ => srgb_to_linear,
+    };
+    let primaries = base
+        .cicp
+        .as_ref()
+        .map_or(ColorPrimaries::Srgb, Cicp::primaries);
+
+    let gain_map = GainMapValues::new(gain_map)?;
+    let weight = info.weight(f64::from(headroom));
+    let channel = |x: (f64, f64, f64)| [x.0 as f32, x.1 as f32, x.2 as f32];
+    let (min, max, gamma) = (
+        channel(info.gain_map_min),
+        channel(info.gain_map_max),
+        channel(info.gamma),
+    );
+    let (base_offset, alternate_offset) =
+        (channel(info.base_offset), channel(info.alternate_offset));
+
+    let layout = PixelLayout::new(base.memory_format);
+    let memory_format = if layout.alpha.is_some() {
+        MemoryFormat::R32g32b32a32Float
+    } else {
+        MemoryFormat::R32g32b32Float
+    };
+    let n_bytes = base.memory_format.n_bytes().usize();
+    let out_n_bytes = memory_format.n_bytes().usize();
+    let width = usize::try_from(base.width)?;
+    let height = usize::try_from(base.height)?;
+
+    let data = mapped(base)?;
+    let mut memory = SharedMemory::new(u64::try_from(width * height * out_n_bytes)?);
+
+    for (y, (row, out_row)) in data
+        .chunks(usize::try_from(base.stride)?)
+        .zip(memory.chunks_exact_mut(width * out_n_bytes))
+        .take(height)
+        .enumerate()
+    {
+        let v = region[1] + (y as f32 + 0.5) / height as f32 * region[3];
+        for (x, (pixel, out_pixel)) in row[..width * n_bytes]
+            .chunks_exact(n_bytes)
+            .zip(out_row.chunks_exact_mut(out_n_bytes))
+            .enumerate()
+        {
+            let u = region[0] + (x as f32 + 0.5) / width as f32 * region[2];
+            let gain = gain_map.sample(u, v);
+
+            let alpha = layout.alpha.map(|i| layout.read(pixel, i));
+            let premultiplied_alpha = alpha.filter(|a| layout.premultiplied && *a > 0.);
+
+            for c in 0..3 {
+                let value = layout.read(pixel, layout.color[c]);
+                let value = to_linear(premultiplied_alpha.map_or(value, |a| value / a));
+
+                let log_boost = if info.linear_gain {
+                    let headroom = f32::powf(2., max[c]);
+                    (1. + (headroom - 1.) * srgb_to_linear(gain[c].clamp(0., 1.))).log2()
+                } else {
+                    let g = gain[c].max(0.).powf(1. / gamma[c]);
+                    min[c] + (max[c] - min[c]) * g
+                };
+                let value = (value + base_offset[c]) * f32::powf(2., log_boost * weight)
+                    - alternate_offset[c];
+
+                out_pixel[c * 4..(c + 1) * 4].copy_from_slice(&value.to_ne_bytes());
+            }
+
+            if let Some(alpha) = alpha {
+                out_pixel[12..16].copy_from_slice(&alpha.to_ne_bytes());
+            }
+        }
+    }
+
+    let mut frame = Frame::new(
+        base.width,
+        base.height,
+        memory_format,
+        memory.into_texture(),
+    );
+    frame.cicp = Some(Cicp::new(primaries, TransferCharacteristics::Linear)).into();
+    frame.delay = base.delay.clone();
+
+    Ok(frame)
+}
+
+/// Decoded gain map values for sampling
+struct GainMapValues {
+    values: Vec<[f32; 3]>,
+    width: usize,
+    height: usize,
+}
+
+impl GainMapValues {
+    fn new(frame: &Frame) -> anyhow::Result<Self> {
+        let layout = PixelLayout::new(frame.memory_format);
+        let n_bytes = frame.memory_format.n_bytes().usize();
+        let width = usize::try_from(frame.width)?;
+        let height = usize::try_from(frame.height)?;
+
+        let data = mapped(frame)?;
+        let values = data
+            .chunks(usize::try_from(frame.stride)?)
+            .take(height)
+            .flat_map(|row| row[..width * n_bytes].chunks_exact(n_bytes))
+            .map(|pixel| layout.color.map(|i| layout.read(pixel, i)))
+            .collect::<Vec<_>>();
+
+        anyhow::ensure!(
+            width > 0 && height > 0 && values.len() == width * height,
+            "Gain map is smaller than announced"
+        );
+
+        Ok(Self {
+            values,
+            width,
+            height,
+        })
+    }
+
+    /// Bilinear sample at relative coordinates
+    fn sample(&self, u: f32, v: f32) -> [f32; 3] {
+        let x = (u * self.width as f32 - 0.5).clamp(0., (self.width - 1) as f32);
+        let y = (v * self.height as f32 - 0.5).clamp(0., (self.height - 1) as f32);
+        let (x0, y0) = (x as usize, y as usize);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (fx, fy) = (x.fract(), y.fract());
+
+        let value = |x: usize, y: usize| self.values[y * self.width + x];
+        let lerp = |a: [f32; 3], b: [f32; 3], t: f32| [0, 1, 2].map(|c| a[c] + (b[c] - a[c]) * t);
+
+        lerp(
+            lerp(value(x0, y0), value(x1, y0), fx),
+            lerp(value(x0, y1), value(x1, y1), fx),
+            fy,
+        )
+    }
+}
+
+fn mapped(frame: &Frame) -> anyhow::Result<memmap::Mmap> {
+    let Texture::MemFd(fd) = &frame.texture;
+    let mmap = unsafe { memmap::Mmap::map(fd.as_raw_fd()) }?;
+    let stride = usize::try_from(frame.stride)?;
+
+    anyhow::ensure!(
+        stride >= usize::try_from(frame.width)? * frame.memory_format.n_bytes().usize(),
+        "Stride is smaller than a row: {frame:?}"
+    );
+    anyhow::ensure!(
+        mmap.len() >= stride * usize::try_from(frame.height)?,
+        "Texture is smaller than announced: {frame:?}"
+    );
+
+    Ok(mmap)
+}
+
+#[test]
+fn ultra_hdr_xmp_test() {
+    let xmp = br#"<rdf:Description hdrgm:Version="1.0" hdrgm:GainMapMax="2"
+        hdrgm:HDRCapacityMax="2" hdrgm:BaseRenditionIsHDR="False"/>"#;
+    let info = GainMapInfo::from_xmp(xmp).unwrap();
+
+    assert_eq!(info.gain_map_max, (2., 2., 2.));
+    assert_eq!(info.gamma, (1., 1., 1.));
+    assert_eq!(info.base_offset, (1. / 64., 1. / 64., 1. / 64.));
+    assert_eq!(info.weight(1.), 0.5);
+    assert_eq!(info.weight(3.), 1.);
+}
+
+#[test]
+fn iso_21496_test() {
+    let mut data = vec![0, 0, 0, 0, 0x08];
+    for value in [4_u32, 0, 8, 0, 8, 4, 0, 0] {
+        data.extend(value.to_be_bytes());
+    }
+    let info = GainMapInfo::from_iso_21496(&data).unwrap();
+
+    assert_eq!(info.alternate_headroom, 2.);
+    assert_eq!(info.gain_map_max, (2., 2., 2.));
+    assert_eq!(info.gamma, (1., 1., 1.));
+}
+
+#[test]
+fn apple_test() {
+    let xmp = br#"<rdf:Description HDRGainMap:HDRGainMapVersion="65536"
+        HDRGainMap:HDRGainMapHeadroom="4"/>"#;
+    let info = GainMapInfo::from_apple(None, Some(xmp)).unwrap();
+
+    assert!(info.linear_gain);
+    assert_eq!(info.gain_map_max, (2., 2., 2.));
+    assert_eq!(info.weight(2.), 1.);
+}
@@ -0,0 +1,577 @@
+//! Color profile conversions via lcms
+
+use crate::{
+    Cicp, ColorConversion, ColorPrimaries, ColorTarget, Frame, MemoryFormat, MemoryFormatBytes,
+    RenderingIntent, TransferCharacteristics,
+};
+use rgb::AsPixels;
+use safe_transmute::error::Error as TsmErr;
+
+use std::any::Any;
+use std::borrow::Cow;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+
+/// Number of prepared transforms kept for reuse
+const TRANSFORM_CACHE_SIZE: usize = 16;
+
+/// Prepared transforms shared by all frames, most recently used last
+///
+/// Conversions run in the loader process, so the cache only lives as long as
+/// the process and is not shared between images decoded in other processes.
+static TRANSFORM_CACHE: Mutex<Vec<CacheEntry>> = Mutex::new(Vec::new());
+
+/// Transforms are only reused for an equal conversion, the hash just speeds up the lookup
+struct CacheEntry {
+    hash: u64,
+    conversion: Conversion<'static>,
+    transform: CachedTransform,
+}
+
+type CachedTransform = Arc<dyn Any + Send + Sync>;
+type SharedTransform<I, O> = lcms2::Transform<I, O, lcms2::GlobalContext, lcms2::DisallowCache>;
+
+/// Converts the frame to the target profile based on its ICC profile or CICP
+///
+/// Frames without color information are treated as sRGB. Returns `false` if
+/// the frame was left untouched. Returns `true` without a conversion if the
+/// ICC profile already is the target profile.
+pub fn apply_transformation(
+    frame: &Frame,
+    mmap: &mut [u8],
+    request: &ColorConversion,
+) -> anyhow::Result<bool> {
+    let target = request.target;
+
+    if target == ColorTarget::Untransformed {
+        return Ok(false);
+    }
+
+    let gray = frame.memory_format.n_channels() <= 2;
+
+    let source = if let Some(iccp) = frame.iccp.as_ref() {
+        // Frames created in the target profile, like converted CMYK data
+        if !gray && is_target_icc(iccp, request) {
+            return Ok(true);
+        }
+        Source::Icc(Cow::Borrowed(iccp))
+    } else if is_srgb(frame.cicp.as_ref(), gray) {
+        if gray || target == ColorTarget::Srgb {
+            return Ok(false);
+        }
+        Source::Srgb
+    } else if let Some(cicp) = frame.cicp.as_ref() {
+        Source::Cicp(*cicp)
+    } else {
+        return Ok(false);
+    };
+
+    transform_frame(source, frame, mmap, request)
+}
+
+/// Profile data for frames that are created in the target profile
+pub fn target_icc(request: &ColorConversion) -> Result<Vec<u8>, lcms2::Error> {
+    target_profile(request, false)?.icc()
+}
+
+/// Ignores the creation date, since generated profiles carry the current time
+fn is_target_icc(iccp: &[u8], request: &ColorConversion) -> bool {
+    const DATE: std::ops::Range<usize> = 24..36;
+
+    let Ok(target) = target_icc(request) else {
+        return false;
+    };
+
+    iccp.len() == target.len()
+        && iccp.len() > DATE.end
+        && iccp[..DATE.start] == target[..DATE.start]
+        && iccp[DATE.end..] == target[DATE.end..]
+}
+
+/// Converts sRGB data, like the result of tone mapping, to the target profile
+pub fn apply_srgb_to_target(
+    frame: &Frame,
+    mmap: &mut [u8],
+    request: &ColorConversion,
+) -> anyhow::Result<()> {
+    let gray = frame.memory_format.n_channels() <= 2;
+
+    if gray
+        || matches!(
+            request.target,
+            ColorTarget::Srgb | ColorTarget::Untransformed
+        )
+    {
+        return Ok(());
+    }
+
+    transform_frame(Source::Srgb, frame, mmap, request)?;
+
+    Ok(())
+}
+
+/// CICP to convert ICC profile data into without clipping its gamut
+///
+/// Uses the profile's own primaries if CICP can express them, and BT.2020
+/// otherwise, with the sRGB transfer function. Grayscale uses gamma 2.2.
+pub fn gamut_cicp(iccp: &[u8], gray: bool) -> Result<Cicp, lcms2::Error> {
+    let profile = lcms2::Profile::new_icc(iccp)?;
+
+    // Like the target profile for all grayscale conversions
+    if gray {
+        return Ok(Cicp::new(
+            ColorPrimaries::Srgb,
+            TransferCharacteristics::Gamma22,
+        ));
+    }
+
+    let own_colorants = colorants(&profile);
+    let primaries = [ColorPrimaries::Srgb, ColorPrimaries::DisplayP3]
+        .into_iter()
+        .find(|primaries| {
+            let Some(chromaticities) = primaries.chromaticities() else {
+                return false;
+            };
+            let Ok(profile) = rgb_profile(chromaticities, &lcms2::ToneCurve::new(1.)) else {
+                return false;
+            };
+
+            own_colorants
+                .iter()
+                .zip(colorants(&profile))
+                .all(|(a, b)| match (a, b) {
+                    (Some(a), Some(b)) => a.iter().zip(b).all(|(a, b)| (a - b).abs() < 0.002),
+                    _ => false,
+                })
+        })
+        .unwrap_or(ColorPrimaries::Bt2020);
+
+    Ok(Cicp::new(primaries, TransferCharacteristics::Srgb))
+}
+
+/// Profile data for CICP that can be handled by an ICC transform
+pub fn cicp_icc(cicp: &Cicp, gray: bool) -> anyhow::Result<Vec<u8>> {
+    let profile =
+        cicp_profile(cicp, gray)?.ok_or_else(|| anyhow::anyhow!("No ICC profile for {cicp:?}"))?;
+
+    Ok(profile.icc()?)
+}
+
+/// Red, green, and blue colorants as XYZ, adapted to D50
+fn colorants(profile: &lcms2::Profile) -> [Option<[f64; 3]>; 3] {
+    [
+        lcms2::TagSignature::RedColorantTag,
+        lcms2::TagSignature::GreenColorantTag,
+        lcms2::TagSignature::BlueColorantTag,
+    ]
+    .map(|tag| match profile.read_tag(tag) {
+        lcms2::Tag::CIEXYZ(xyz) => Some([xyz.X, xyz.Y, xyz.Z]),
+        _ => None,
+    })
+}
+
+/// Color information the data is converted from
+#[derive(Hash, PartialEq, Eq)]
+enum Source<'a> {
+    Icc(Cow<'a, [u8]>),
+    Cicp(Cicp),
+    Srgb,
+}
+
+/// Everything a transform depends on
+#[derive(Hash, PartialEq, Eq)]
+struct Conversion<'a> {
+    source: Source<'a>,
+    request: Cow<'a, ColorConversion>,
+    rendering_intent: RenderingIntent,
+    pixel_format: u32,
+    target_pixel_format: u32,
+}
+
+impl Conversion<'_> {
+    /// Copy that owns the profile data, for the cache
+    fn clone_owned(&self) -> Conversion<'static> {
+        Conversion {
+            source: match &self.source {
+                Source::Icc(icc_profile) => Source::Icc(Cow::Owned(icc_profile.to_vec())),
+                Source::Cicp(cicp) => Source::Cicp(*cicp),
+                Source::Srgb => Source::Srgb,
+            },
+            request: Cow::Owned(self.request.clone().into_owned()),
+            rendering_intent: self.rendering_intent,
+            pixel_format: self.pixel_format,
+            target_pixel_format: self.target_pixel_format,
+        }
+    }
+}
+
+fn transform_frame(
+    source: Source,
+    frame: &Frame,
+    mmap: &mut [u8],
+    request: &ColorConversion,
+) -> anyhow::Result<bool> {
+    let memory_format = frame.memory_format;
+
+    let pixel_format = lcms_pixel_format(memory_format).0;
+    let conversion = Conversion {
+        source,
+        request: Cow::Borrowed(request),
+        rendering_intent: request
+            .rendering_intent
+            .or(*frame.rendering_intent)
+            .unwrap_or_default(),
+        pixel_format,
+        target_pixel_format: pixel_format,
+    };
+
+    let transformed = match memory_format.n_bytes() {
+        MemoryFormatBytes::B1 => transform::<u8>(&conversion, memory_format, mmap),
+        MemoryFormatBytes::B2 => {
+            let buf =
+                safe_transmute::transmute_many_pedantic_mut(mmap).map_err(TsmErr::without_src)?;
+            transform::<u16>(&conversion, memory_format, buf)
+        }
+        MemoryFormatBytes::B3 => {
+            transform::<rgb::RGB<u8>>(&conversion, memory_format, mmap.as_pixels_mut())
+        }
+        MemoryFormatBytes::B4 => {
+            transform::<rgb::RGBA<u8>>(&conversion, memory_format, mmap.as_pixels_mut())
+        }
+        MemoryFormatBytes::B6 => {
+            let buf =
+                safe_transmute::transmute_many_pedantic_mut(mmap).map_err(TsmErr::without_src)?;
+            transform::<rgb::RGB<u16>>(&conversion, memory_format, buf.as_pixels_mut())
+        }
+        MemoryFormatBytes::B8 => {
+            let buf =
+                safe_transmute::transmute_many_pedantic_mut(mmap).map_err(TsmErr::without_src)?;
+            transform::<rgb::RGBA<u16>>(&conversion, memory_format, buf.as_pixels_mut())
+        }
+        MemoryFormatBytes::B12 => {
+            let buf =
+                safe_transmute::transmute_many_pedantic_mut(mmap).map_err(TsmErr::without_src)?;
+            transform::<rgb::RGB<u32>>(&conversion, memory_format, buf.as_pixels_mut())
+        }
+        MemoryFormatBytes::B16 => {
+            let buf =
+                safe_transmute::transmute_many_pedantic_mut(mmap).map_err(TsmErr::without_src)?;
+            transform::<rgb::RGBA<u32>>(&conversion, memory_format, buf.as_pixels_mut())
+        }
+    }?;
+
+    Ok(transformed)
+}
+
+fn source_profile(source: &Source, gray: bool) -> Result<Option<lcms2::Profile>, lcms2::Error> {
+    match source {
+        Source::Icc(icc_profile) => lcms2::Profile::new_icc(icc_profile).map(Some),
+        Source::Cicp(cicp) => cicp_profile(cicp, gray),
+        Source::Srgb => Ok(Some(lcms2::Profile::new_srgb())),
+    }
+}
+
+/// Grayscale is always converted to a gray profile with gamma 2.2
+fn target_profile(request: &ColorConversion, gray: bool) -> Result<lcms2::Profile, lcms2::Error> {
+    if gray {
+        return lcms2::Profile::new_gray(
+            lcms2_sys::ffi::CIExyY::d50(),
+            &lcms2::ToneCurve::new(2.2),
+        );
+    }
+
+    match request.target {
+        ColorTarget::Srgb | ColorTarget::Untransformed => Ok(lcms2::Profile::new_srgb()),
+        ColorTarget::DisplayP3 => rgb_profile(
+            ColorPrimaries::DisplayP3.chromaticities().unwrap(),
+            &srgb_curve()?,
+        ),
+        ColorTarget::AdobeRgb => rgb_profile(
+            [(0.64, 0.33), (0.21, 0.71), (0.15, 0.06), (0.3127, 0.3290)],
+            &lcms2::ToneCurve::new(563. / 256.),
+        ),
+        ColorTarget::Icc => lcms2::Profile::new_icc(&request.target_icc),
+    }
+}
+
+fn is_srgb(cicp: Option<&Cicp>, gray: bool) -> bool {
+    let Some(cicp) = cicp else {
+        return true;
+    };
+
+    let srgb_primaries = matches!(
+        cicp.primaries(),
+        ColorPrimaries::Srgb | ColorPrimaries::Unspecified
+    );
+
+    cicp.transfer() == TransferCharacteristics::Srgb && (gray || srgb_primaries)
+}
+
+/// Profile for CICP that can be handled by an ICC transform
+///
+/// HDR transfer functions are left to tone mapping.
+fn cicp_profile(cicp: &Cicp, gray: bool) -> Result<Option<lcms2::Profile>, lcms2::Error> {
+    let primaries = match cicp.primaries() {
+        ColorPrimaries::Unspecified => ColorPrimaries::Srgb,
+        primaries => primaries,
+    };
+
+    let curve = match cicp.transfer() {
+        TransferCharacteristics::Srgb => srgb_curve()?,
+        TransferCharacteristics::Bt709
+        | TransferCharacteristics::Bt601
+        | TransferCharacteristics::Bt2020TenBit
+        | TransferCharacteristics::Bt2020TwelveBit => {
+            // Inverse of the BT.709 OETF
+            lcms2::ToneCurve::new_parametric(
+                4,
+                &[1. / 0.45, 1. / 1.099, 0.099 / 1.099, 1. / 4.5, 0.081],
+            )?
+        }
+        TransferCharacteristics::Gamma22 => lcms2::ToneCurve::new(2.2),
+        TransferCharacteristics::Gamma28 => lcms2::ToneCurve::new(2.8),
+        _ => return Ok(None),
+    };
+
+    if gray {
+        return lcms2::Profile::new_gray(lcms2_sys::ffi::CIExyY::d50(), &curve).map(Some);
+    }
+
+    let Some(chromaticities) = primaries.chromaticities() else {
+        return Ok(None);
+    };
+
+    rgb_profile(chromaticities, &curve).map(Some)
+}
+
+/// ICC profile for data with a plain gamma, like PNGs with `gAMA` and `cHRM` chunks
+///
+/// The gamma is the decoding exponent. Chromaticities default to sRGB.
+pub fn gamma_icc_profile(
+    gamma: f64,
+    chromaticities: Option<[(f64, f64); 4]>,
+    gray: bool,
+) -> Option<Vec<u8>> {
+    let curve = lcms2::ToneCurve::new(gamma);
+
+    let profile = if gray {
+        lcms2::Profile::new_gray(lcms2_sys::ffi::CIExyY::d50(), &curve)
+    } else {
+        let chromaticities = chromaticities.or_else(|| ColorPrimaries::Srgb.chromaticities())?;
+        rgb_profile(chromaticities, &curve)
+    };
+
+    profile.and_then(|profile| profile.icc()).ok()
+}
+
+fn rgb_profile(
+    chromaticities: [(f64, f64); 4],
+    curve: &lcms2::ToneCurve,
+) -> Result<lcms2::Profile, lcms2::Error> {
+    let [red, green, blue, white] = chromaticities;
+    let xyy = |(x, y): (f64, f64)| lcms2_sys::ffi::CIExyY { x, y, Y: 1. };
+
+    lcms2::Profile::new_rgb(
+        &xyy(white),
+        &lcms2_sys::ffi::CIExyYTRIPLE {
+            Red: xyy(red),
+            Green: xyy(green),
+            Blue: xyy(blue),
+        },
+        &[curve, curve, curve],
+    )
+}
+
+fn srgb_curve() -> Result<lcms2::ToneCurve, lcms2::Error> {
+    lcms2::ToneCurve::new_parametric(4, &[2.4, 1. / 1.055, 0.055 / 1.055, 1. / 12.92, 0.04045])
+}
+
+/// Returns `false` if the conversion is not supported
+fn transform<F: Copy + Send + Sync + 'static>(
+    conversion: &Conversion,
+    memory_format: MemoryFormat,
+    buf: &mut [F],
+) -> Result<bool, lcms2::Error> {
+    let transform = shared_transform(conversion, || {
+        let gray = memory_format.n_channels() <= 2;
+        let Some(src_profile) = source_profile(&conversion.source, gray)? else {
+            return Ok(None);
+        };
+        let target_profile = target_profile(&conversion.request, gray)?;
+        let icc_pixel_format = lcms_pixel_format(memory_format);
+
+        lcms2::Transform::new_flags_context(
+            lcms2::GlobalContext::new(),
+            &src_profile,
+            icc_pixel_format,
+            &target_profile,
+            icc_pixel_format,
+            lcms_intent(conversion.rendering_intent),
+            lcms_flags(&conversion.request),
+        )
+        .map(Some)
+    })?;
+
+    let Some(transform) = transform else {
+        return Ok(false);
+    };
+
+    transform.transform_in_place(buf);
+
+    Ok(true)
+}
+
+/// Converts CMYK data directly into the target profile
+///
+/// The target is sRGB for [`ColorTarget::Untransformed`].
+pub fn transform_cmyk<I: Copy + Send + Sync + 'static, O: Copy + Send + Sync + 'static>(
+    cmyk_profile: &[u8],
+    input: (&[I], lcms2::PixelFormat),
+    output: (&mut [O], lcms2::PixelFormat),
+    request: &ColorConversion,
+) -> Result<(), lcms2::Error> {
+    let conversion = Conversion {
+        source: Source::Icc(Cow::Borrowed(cmyk_profile)),
+        request: Cow::Borrowed(request),
+        rendering_intent: request.rendering_intent.unwrap_or_default(),
+        pixel_format: input.1 .0,
+        target_pixel_format: output.1 .0,
+    };
+
+    let transform = shared_transform(&conversion, || {
+        lcms2::Transform::new_flags_context(
+            lcms2::GlobalContext::new(),
+            &lcms2::Profile::new_icc(cmyk_profile)?,
+            input.1,
+            &target_profile(request, false)?,
+            output.1,
+            lcms_intent(conversion.rendering_intent),
+            lcms_flags(request),
+        )
+        .map(Some)
+    })?;
+
+    if let Some(transform) = transform {
+        transform.transform_pixels(input.0, output.0);
+    }
+
+    Ok(())
+}
+
+/// Prepared transform from the cache, or newly created and added to the cache
+fn shared_transform<I: Send + Sync + 'static, O: Send + Sync + 'static>(
+    conversion: &Conversion,
+    create: impl FnOnce() -> Result<Option<SharedTransform<I, O>>, lcms2::Error>,
+) -> Result<Option<Arc<SharedTransform<I, O>>>, lcms2::Error> {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    conversion.hash(&mut hasher);
+    let hash = hasher.finish();
+
+    if let Some(transform) = cached_transform(hash, conversion) {
+        return Ok(Some(transform));
+    }
+
+    let Some(transform) = create()? else {
+        return Ok(None);
+    };
+    let transform = Arc::new(transform);
+
+    let mut cache = TRANSFORM_CACHE.lock().unwrap();
+    if cache.len() >= TRANSFORM_CACHE_SIZE {
+        cache.remove(0);
+    }
+    cache.push(CacheEntry {
+        hash,
+        conversion: conversion.clone_owned(),
+        transform: transform.clone(),
+    });
+
+    Ok(Some(transform))
+}
+
+fn cached_transform<I: Send + Sync + 'static, O: Send + Sync + 'static>(
+    hash: u64,
+    conversion: &Conversion,
+) -> Option<Arc<SharedTransform<I, O>>> {
+    let mut cache = TRANSFORM_CACHE.lock().unwrap();
+    let index = cache.iter().position(|entry| {
+        entry.hash == hash
+            && entry.conversion == *conversion
+            && entry.transform.is::<SharedTransform<I, O>>()
+    })?;
+
+    // Move to the end to mark as most recently used
+    let entry = cache.remove(index);
+    let transform = entry.transform.clone().downcast().ok();
+    cache.push(entry);
+
+    transform
+}
+
+/// Without the cache transforms can be shared between threads
+fn lcms_flags(request: &ColorConversion) -> lcms2::Flags<lcms2::DisallowCache> {
+    if request.black_point_compensation {
+        lcms2::Flags::NO_CACHE | lcms2::Flags::BLACKPOINT_COMPENSATION
+    } else {
+        lcms2::Flags::NO_CACHE
+    }
+}
+
+const fn lcms_intent(intent: RenderingIntent) -> lcms2::Intent {
+    match intent {
+        RenderingIntent::Perceptual => lcms2::Intent::Perceptual,
+        RenderingIntent::RelativeColorimetric => lcms2::Intent::RelativeColorimetric,
+        RenderingIntent::Saturation => lcms2::Intent::Saturation,
+        RenderingIntent::AbsoluteColorimetric => lcms2::Intent::AbsoluteColorimetric,
+    }
+}
+
+const fn lcms_pixel_format(format: MemoryFormat) -> lcms2::PixelFormat {
+    match format {
+        MemoryFormat::B8g8r8a8Premultiplied => premul(lcms2::PixelFormat::BGRA_8),
+        MemoryFormat::A8r8g8b8Premultiplied => premul(lcms2::PixelFormat::ARGB_8),
+        MemoryFormat::R8g8b8a8Premultiplied => premul(lcms2::PixelFormat::RGBA_8),
+        MemoryFormat::B8g8r8a8 => lcms2::PixelFormat::BGRA_8,
+        MemoryFormat::A8r8g8b8 => lcms2::PixelFormat::ARGB_8,
+        MemoryFormat::R8g8b8a8 => lcms2::PixelFormat::RGBA_8,
+        MemoryFormat::A8b8g8r8 => lcms2::PixelFormat::ABGR_8,
+        MemoryFormat::R8g8b8 => lcms2::PixelFormat::RGB_8,
+        MemoryFormat::B8g8r8 => lcms2::PixelFormat::BGR_8,
+        MemoryFormat::R16g16b16 => lcms2::PixelFormat::RGB_16,
+        MemoryFormat::R16g16b16a16Premultiplied => premul(lcms2::PixelFormat::RGBA_16),
+        MemoryFormat::R16g16b16a16 => lcms2::PixelFormat::RGBA_16,
+        MemoryFormat::R16g16b16Float => lcms2::PixelFormat::RGB_HALF_FLT,
+        MemoryFormat::R16g16b16a16Float => lcms2::PixelFormat::RGBA_HALF_FLT,
+        MemoryFormat::R32g32b32Float => lcms2::PixelFormat::RGB_FLT,
+        MemoryFormat::R32g32b32a32FloatPremultiplied => premul(lcms2::PixelFormat::RGBA_FLT),
+        MemoryFormat::R32g32b32a32Float => lcms2::PixelFormat::RGBA_FLT,
+        MemoryFormat::G8a8 => lcms2::PixelFormat::GRAYA_8,
+        MemoryFormat::G8 => lcms2::PixelFormat::GRAY_8,
+        MemoryFormat::G16a16 => lcms2::PixelFormat::GRAYA_16,
+        MemoryFormat::G16 => lcms2::PixelFormat::GRAY_16,
+    }
+}
+
+const fn premul(format: lcms2::PixelFormat) -> lcms2::PixelFormat {
+    let mut bytes = format.0;
+    bytes |= 0b1 << 23;
+    lcms2::PixelFormat(bytes)
+}
+
+#[test]
+fn premul_test() {
+    assert!(!lcms2::PixelFormat::RGBA_8.premultiplied());
+    assert!(premul(lcms2::PixelFormat::RGBA_8).premultiplied());
+}
+
+#[test]
+fn is_target_icc_test() {
+    let request = ColorConversion {
+        target: ColorTarget::DisplayP3,
+        ..Default::default()
+    };
+    let iccp = target_icc(&request).unwrap();
+
+    assert!(is_target_icc(&iccp, &request));
+    assert!(!is_target_icc(&iccp, &ColorConversion::default()));
+}
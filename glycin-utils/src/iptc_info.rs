@@ -0,0 +1,106 @@
+//! Parsing of IPTC-IIM data into [`IptcInfo`]
+
+use crate::IptcInfo;
+
+/// Tag marker starting each dataset
+const TAG_MARKER: u8 = 0x1C;
+
+/// Coded character set escape sequence for UTF-8
+const UTF8: &[u8] = b"\x1B%G";
+
+impl IptcInfo {
+    /// Parses IPTC-IIM datasets
+    pub fn parse(iptc: &[u8]) -> Option<Self> {
+        let datasets = datasets(iptc);
+        if datasets.is_empty() {
+            return None;
+        }
+
+        // Without a coded character set, text is assumed to be UTF-8 if valid
+        let latin1 = datasets
+            .iter()
+            .find(|(record, dataset, _)| (*record, *dataset) == (1, 90))
+            .is_some_and(|(_, _, value)| *value != UTF8);
+
+        let text = |value: &[u8]| -> Option<String> {
+            let text = match std::str::from_utf8(value) {
+                Ok(text) if !latin1 => text.to_string(),
+                _ => value.iter().map(|x| char::from(*x)).collect(),
+            };
+            let text = text.trim().to_string();
+            Some(text).filter(|x| !x.is_empty())
+        };
+        let field = |dataset: u8| {
+            datasets
+                .iter()
+                .find(|x| (x.0, x.1) == (2, dataset))
+                .and_then(|x| text(x.2))
+        };
+        let fields = |dataset: u8| -> Vec<String> {
+            datasets
+                .iter()
+                .filter(|x| (x.0, x.1) == (2, dataset))
+                .filter_map(|x| text(x.2))
+                .collect()
+        };
+
+        Some(Self {
+            object_name: field(5).into(),
+            headline: field(105).into(),
+            caption: field(120).into(),
+            keywords: fields(25),
+            by_line: fields(80),
+            credit: field(110).into(),
+            source: field(115).into(),
+            copyright_notice: field(116).into(),
+            city: field(90).into(),
+            country: field(101).into(),
+            date_created: field(55).and_then(|x| date(&x)).into(),
+        })
+    }
+}
+
+/// Record number, dataset number, and value of all datasets
+fn datasets(mut iptc: &[u8]) -> Vec<(u8, u8, &[u8])> {
+    let mut datasets = Vec::new();
+
+    while let [TAG_MARKER, record, dataset, size0, size1, rest @ ..] = iptc {
+        let size = u16::from_be_bytes([*size0, *size1]);
+        // Extended datasets are only used for binary data like previews
+        if size & 0x8000 != 0 {
+            break;
+        }
+
+        let Some(value) = rest.get(..usize::from(size)) else {
+            break;
+        };
+        datasets.push((*record, *dataset, value));
+        iptc = &rest[usize::from(size)..];
+    }
+
+    datasets
+}
+
+/// Formats `CCYYMMDD` as ISO 8601
+fn date(value: &str) -> Option<String> {
+    if value.len() != 8 || !value.bytes().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+
+    Some(format!("{}-{}-{}", &value[..4], &value[4..6], &value[6..]))
+}
+
+#[test]
+fn iptc_info_test() {
+    let mut iptc = Vec::new();
+    for (dataset, value) in [(25, "cat"), (25, "dog"), (120, "Caption"), (55, "20230401")] {
+        iptc.extend([TAG_MARKER, 2, dataset]);
+        iptc.extend(u16::try_from(value.len()).unwrap().to_be_bytes());
+        iptc.extend(value.as_bytes());
+    }
+
+    let iptc_info = IptcInfo::parse(&iptc).unwrap();
+    assert_eq!(iptc_info.keywords, ["cat", "dog"]);
+    assert_eq!(iptc_info.caption.as_deref(), Some("Caption"));
+    assert_eq!(iptc_info.date_created.as_deref(), Some("2023-04-01"));
+}
@@ -6,7 +6,20 @@
 #[doc(hidden)]
 pub mod image_rs;
 
+mod cmyk;
+mod exif_info;
+mod gain_map;
+mod icc;
+mod iptc_info;
+mod localization;
+mod orientation;
+mod tone_mapping;
+mod xml;
+
 pub use anyhow;
+pub use cmyk::{cmyk_frame, CmykData};
+pub use icc::gamma_icc_profile;
+pub use localization::{localized_text, xmp_title_description};
 pub use std::os::unix::net::UnixStream;
 
 use anyhow::Context;
@@ -14,6 +27,7 @@ use gettextrs::gettext;
 use serde::{Deserialize, Serialize};
 use zbus::zvariant::{self, Optional, Type};
 
+use std::collections::BTreeMap;
 use std::ffi::CString;
 use std::ops::{Deref, DerefMut};
 use std::os::fd::AsRawFd;
@@ -73,6 +87,15 @@ pub struct DecodingRequest {
 pub struct DecodingDetails {
     pub mime_type: String,
     pub base_dir: Optional<std::path::PathBuf>,
+    /// Apply orientation and similar transformations stored in the image
+    pub apply_transformations: bool,
+    /// Only metadata is requested, no frames will be decoded
+    ///
+    /// Loaders can skip preparations for decoding. `init` can be called
+    /// again for further images in this mode.
+    pub info_only: bool,
+    /// Preferred languages for localized metadata, most preferred first
+    pub languages: Vec<String>,
 }
 
 #[derive(Deserialize, Serialize, Type, Debug, Clone, Default)]
@@ -80,6 +103,91 @@ pub struct FrameRequest {
     pub scale: Optional<(u32, u32)>,
     /// Instruction to only decode part of the image
     pub clip: Optional<(u32, u32, u32, u32)>,
+    /// Part index and name of the layer to decode
+    ///
+    /// The name can also be the full name of a single channel.
+    pub layer: Optional<(u32, String)>,
+    /// Index in [`ImageInfo::auxiliary_images`] of the image to decode instead
+    pub auxiliary_image: Optional<u32>,
+    /// Color conversion applied after decoding
+    pub color_conversion: ColorConversion,
+    /// Tone mapping of HDR content, applied after decoding
+    pub tone_mapping: Optional<ToneMapping>,
+    /// Headroom in stops of the display for which HDR is reconstructed from a gain map
+    ///
+    /// The frame is returned as linear floats that exceed 1.0 for content
+    /// brighter than SDR white. Ignored if the image has no gain map.
+    pub hdr_headroom: Optional<f32>,
+}
+
+/// Conversion into a target color profile
+#[derive(Deserialize, Serialize, Type, Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ColorConversion {
+    pub target: ColorTarget,
+    /// Profile data for [`ColorTarget::Icc`]
+    pub target_icc: Vec<u8>,
+    /// Defaults to [`Frame::rendering_intent`], or perceptual
+    pub rendering_intent: Optional<RenderingIntent>,
+    pub black_point_compensation: bool,
+}
+
+/// Color profile that frames are converted into
+#[derive(Deserialize, Serialize, Type, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ColorTarget {
+    #[default]
+    Srgb,
+    DisplayP3,
+    AdobeRgb,
+    /// Profile given in [`ColorConversion::target_icc`]
+    Icc,
+    /// Keep the data and color information as is
+    Untransformed,
+}
+
+/// Rendering intent for color profile conversions
+#[derive(Deserialize, Serialize, Type, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum RenderingIntent {
+    #[default]
+    Perceptual,
+    RelativeColorimetric,
+    Saturation,
+    AbsoluteColorimetric,
+}
+
+/// Tone mapping of HDR content into SDR range
+#[derive(Deserialize, Serialize, Type, Debug, Clone, Copy, Default, PartialEq)]
+pub struct ToneMapping {
+    pub operator: ToneMappingOperator,
+    /// Exposure adjustment in stops, applied before the operator
+    pub exposure: f32,
+}
+
+impl ToneMapping {
+    pub fn new(operator: ToneMappingOperator) -> Self {
+        Self {
+            operator,
+            exposure: 0.,
+        }
+    }
+
+    pub fn exposure(mut self, exposure: f32) -> Self {
+        self.exposure = exposure;
+        self
+    }
+}
+
+/// Curve used to map HDR values into SDR range
+#[derive(Deserialize, Serialize, Type, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ToneMappingOperator {
+    /// Values above SDR white are clipped
+    Clip,
+    /// Reinhard operator applied to the luminance
+    #[default]
+    Reinhard,
+    /// Filmic curve by John Hable
+    Hable,
+    /// Approximation of the ACES filmic curve
+    Aces,
 }
 
 /// Various image metadata
@@ -89,10 +197,32 @@ pub struct ImageInfo {
     pub height: u32,
     pub format_name: String,
     pub exif: Optional<Vec<u8>>,
+    /// Parsed EXIF metadata
+    pub exif_info: Optional<ExifInfo>,
     pub xmp: Optional<Vec<u8>>,
+    /// IPTC-IIM datasets
+    pub iptc: Optional<Vec<u8>>,
+    /// Parsed IPTC-IIM metadata
+    pub iptc_info: Optional<IptcInfo>,
+    /// Title in the preferred language
+    pub title: Optional<String>,
+    /// Description, like alt text, in the preferred language
+    pub description: Optional<String>,
+    /// Textual key/value metadata, like PNG text chunks
+    pub key_value: Optional<BTreeMap<String, String>>,
+    /// Size of the largest embedded thumbnail, if there is one
+    pub thumbnail_size: Optional<(u32, u32)>,
     pub transformations_applied: bool,
     pub dimensions_text: Optional<String>,
     pub dimensions_inch: Optional<(f64, f64)>,
+    /// Layers of all parts, for formats like OpenEXR
+    pub layers: Optional<Vec<ImageLayer>>,
+    /// Auxiliary images like depth maps that belong to the image
+    pub auxiliary_images: Optional<Vec<AuxiliaryImage>>,
+    /// Number of frames of an animation, if known
+    pub frame_count: Optional<u32>,
+    /// How often an animation is played, zero meaning indefinitely
+    pub loop_count: Optional<u32>,
 }
 
 impl ImageInfo {
@@ -102,12 +232,184 @@ impl ImageInfo {
             height,
             format_name,
             exif: None.into(),
+            exif_info: None.into(),
             xmp: None.into(),
+            iptc: None.into(),
+            iptc_info: None.into(),
+            title: None.into(),
+            description: None.into(),
+            key_value: None.into(),
+            thumbnail_size: None.into(),
             transformations_applied: false,
             dimensions_text: None.into(),
             dimensions_inch: None.into(),
+            layers: None.into(),
+            auxiliary_images: None.into(),
+            frame_count: None.into(),
+            loop_count: None.into(),
+        }
+    }
+
+    /// Sets `dimensions_inch` from the horizontal and vertical pixels per inch
+    ///
+    /// Invalid resolutions are ignored.
+    pub fn set_resolution(&mut self, x_ppi: f64, y_ppi: f64) {
+        if x_ppi.is_normal() && y_ppi.is_normal() && x_ppi > 0. && y_ppi > 0. {
+            self.dimensions_inch = Some((
+                f64::from(self.width) / x_ppi,
+                f64::from(self.height) / y_ppi,
+            ))
+            .into();
+        }
+    }
+}
+
+/// Commonly used EXIF metadata
+#[derive(Deserialize, Serialize, Type, Debug, Clone, Default, PartialEq)]
+pub struct ExifInfo {
+    /// Capture time in ISO 8601 format, with time zone if known
+    pub date_time_original: Optional<String>,
+    pub image_description: Optional<String>,
+    /// Camera manufacturer
+    pub make: Optional<String>,
+    /// Camera model
+    pub model: Optional<String>,
+    pub lens_make: Optional<String>,
+    pub lens_model: Optional<String>,
+    /// Exposure time in seconds as numerator and denominator
+    pub exposure_time: Optional<(u32, u32)>,
+    pub f_number: Optional<f64>,
+    /// ISO speed
+    pub iso: Optional<u32>,
+    /// Focal length in millimeters
+    pub focal_length: Optional<f64>,
+    /// Latitude and longitude in degrees, negative for south and west
+    pub gps_position: Optional<(f64, f64)>,
+    /// Altitude in meters, negative below sea level
+    pub gps_altitude: Optional<f64>,
+    /// Orientation as defined by EXIF, from 1 to 8
+    pub orientation: Optional<u16>,
+    /// Horizontal and vertical pixels per inch
+    pub resolution: Optional<(f64, f64)>,
+}
+
+/// Commonly used IPTC-IIM metadata from the application record
+#[derive(Deserialize, Serialize, Type, Debug, Clone, Default, PartialEq, Eq)]
+pub struct IptcInfo {
+    /// Object name, often used as title
+    pub object_name: Optional<String>,
+    pub headline: Optional<String>,
+    /// Caption or abstract
+    pub caption: Optional<String>,
+    pub keywords: Vec<String>,
+    /// Creators
+    pub by_line: Vec<String>,
+    pub credit: Optional<String>,
+    pub source: Optional<String>,
+    pub copyright_notice: Optional<String>,
+    pub city: Optional<String>,
+    pub country: Optional<String>,
+    /// Creation date in ISO 8601 format
+    pub date_created: Optional<String>,
+}
+
+/// Auxiliary image that belongs to the image, like a depth map
+///
+/// Auxiliary images containing data instead of colors, like depth values, are
+/// decoded without color conversion. See [`AuxiliaryImageKind::has_colors`].
+#[derive(Deserialize, Serialize, Type, Debug, Clone, Default, PartialEq)]
+pub struct AuxiliaryImage {
+    pub kind: AuxiliaryImageKind,
+    /// Type as stored in the file, like `urn:mpeg:hevc:2015:auxid:2`
+    pub auxiliary_type: String,
+    pub width: u32,
+    pub height: u32,
+    /// XMP metadata of the auxiliary image, often describing its values
+    pub xmp: Optional<Vec<u8>>,
+    /// Parameters for applying a gain map, if known
+    pub gain_map: Optional<GainMapInfo>,
+}
+
+/// Parameters for applying a gain map as defined by ISO 21496-1
+///
+/// Applying the gain map leads from the base image to the alternate
+/// rendition. Values are given per red, green, and blue channel.
+#[derive(Deserialize, Serialize, Type, Debug, Clone, Copy, Default, PartialEq)]
+pub struct GainMapInfo {
+    /// Log2 of the gain for gain map values of 0
+    pub gain_map_min: (f64, f64, f64),
+    /// Log2 of the gain for gain map values of 1
+    pub gain_map_max: (f64, f64, f64),
+    /// Gamma of the encoded gain map values
+    pub gamma: (f64, f64, f64),
+    pub base_offset: (f64, f64, f64),
+    pub alternate_offset: (f64, f64, f64),
+    /// Log2 headroom of the base image
+    pub base_headroom: f64,
+    /// Log2 headroom of the alternate rendition
+    pub alternate_headroom: f64,
+    /// Gain map values are sRGB encoded and scale the gain linearly
+    ///
+    /// Used for Apple's HDR gain maps, which give a gain of `1 + (headroom
+    /// - 1) * gain`, with the headroom from [`Self::gain_map_max`].
+    pub linear_gain: bool,
+}
+
+#[derive(Deserialize, Serialize, Type, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AuxiliaryImageKind {
+    Alpha,
+    /// Depth or disparity map
+    Depth,
+    /// Gain map for displaying the image in HDR
+    GainMap,
+    /// Segmentation matte, like for portrait effects
+    Matte,
+    /// Larger preview of the image
+    Preview,
+    /// Image from another viewpoint, like the second image of a stereo pair
+    View,
+    /// Further color image without a more specific kind
+    Image,
+    /// Data like unknown HEIF auxiliary images
+    #[default]
+    Other,
+}
+
+impl AuxiliaryImageKind {
+    /// Kind for auxiliary types used in HEIF and by Apple
+    pub fn from_auxiliary_type(auxiliary_type: &str) -> Self {
+        match auxiliary_type {
+            "urn:mpeg:avc:2015:auxid:1"
+            | "urn:mpeg:hevc:2015:auxid:1"
+            | "urn:mpeg:mpegB:cicp:systems:auxiliary:alpha" => Self::Alpha,
+            "urn:mpeg:hevc:2015:auxid:2" | "urn:mpeg:mpegB:cicp:systems:auxiliary:depth" => {
+                Self::Depth
+            }
+            x if x.ends_with(":aux:hdrgainmap") => Self::GainMap,
+            x if x.ends_with("matte") => Self::Matte,
+            _ => Self::Other,
         }
     }
+
+    /// Whether the image contains colors that can be converted like for the main image
+    pub const fn has_colors(self) -> bool {
+        matches!(self, Self::Preview | Self::View | Self::Image)
+    }
+}
+
+/// Named layer within a part of an image
+#[derive(Deserialize, Serialize, Type, Debug, Clone, Default, PartialEq)]
+pub struct ImageLayer {
+    /// Index of the part containing the layer
+    pub part: u32,
+    /// Name of the part, empty if not set
+    pub part_name: String,
+    /// Name of the layer, empty for channels without a layer prefix
+    pub name: String,
+    /// Channel names without the layer prefix, like `R`, `G`, `B`, or `Z`
+    pub channels: Vec<String>,
+    pub width: u32,
+    pub height: u32,
 }
 
 #[derive(Deserialize, Serialize, Type, Debug)]
@@ -118,7 +420,10 @@ pub struct Frame {
     pub memory_format: MemoryFormat,
     pub texture: Texture,
     pub iccp: Optional<Vec<u8>>,
-    pub cicp: Optional<Vec<u8>>,
+    /// Color signaling, only used if `iccp` is not set
+    pub cicp: Optional<Cicp>,
+    /// Rendering intent given by the image
+    pub rendering_intent: Optional<RenderingIntent>,
     pub delay: Optional<Duration>,
 }
 
@@ -134,11 +439,175 @@ impl Frame {
             texture,
             iccp: None.into(),
             cicp: None.into(),
+            rendering_intent: None.into(),
             delay: None.into(),
         }
     }
 }
 
+/// Coding-independent code points as defined in ITU-T H.273
+///
+/// Loaders always output RGB, so the matrix coefficients are only
+/// informational.
+#[derive(Deserialize, Serialize, Type, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Cicp {
+    pub color_primaries: u8,
+    pub transfer_characteristics: u8,
+    pub matrix_coefficients: u8,
+    pub video_full_range_flag: bool,
+}
+
+impl Cicp {
+    /// sRGB primaries with linear transfer
+    pub const LINEAR_SRGB: Self = Self::new(ColorPrimaries::Srgb, TransferCharacteristics::Linear);
+
+    pub const fn new(
+        color_primaries: ColorPrimaries,
+        transfer_characteristics: TransferCharacteristics,
+    ) -> Self {
+        Self {
+            color_primaries: color_primaries.code(),
+            transfer_characteristics: transfer_characteristics.code(),
+            matrix_coefficients: 0,
+            video_full_range_flag: true,
+        }
+    }
+
+    pub const fn primaries(&self) -> ColorPrimaries {
+        ColorPrimaries::from_code(self.color_primaries)
+    }
+
+    pub const fn transfer(&self) -> TransferCharacteristics {
+        TransferCharacteristics::from_code(self.transfer_characteristics)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorPrimaries {
+    /// BT.709
+    Srgb,
+    Unspecified,
+    /// BT.470 System B, G
+    Bt601_625,
+    /// SMPTE 170M
+    Bt601_525,
+    /// BT.2020 and BT.2100
+    Bt2020,
+    /// SMPTE RP 431-2
+    DciP3,
+    /// SMPTE EG 432-1
+    DisplayP3,
+    Other(u8),
+}
+
+impl ColorPrimaries {
+    pub const fn from_code(code: u8) -> Self {
+        match code {
+            1 => Self::Srgb,
+            2 => Self::Unspecified,
+            5 => Self::Bt601_625,
+            6 => Self::Bt601_525,
+            9 => Self::Bt2020,
+            11 => Self::DciP3,
+            12 => Self::DisplayP3,
+            code => Self::Other(code),
+        }
+    }
+
+    pub const fn code(self) -> u8 {
+        match self {
+            Self::Srgb => 1,
+            Self::Unspecified => 2,
+            Self::Bt601_625 => 5,
+            Self::Bt601_525 => 6,
+            Self::Bt2020 => 9,
+            Self::DciP3 => 11,
+            Self::DisplayP3 => 12,
+            Self::Other(code) => code,
+        }
+    }
+
+    /// Chromaticities of red, green, blue, and the white point as xy
+    pub const fn chromaticities(self) -> Option<[(f64, f64); 4]> {
+        const D65: (f64, f64) = (0.3127, 0.3290);
+
+        match self {
+            Self::Srgb => Some([(0.640, 0.330), (0.300, 0.600), (0.150, 0.060), D65]),
+            Self::Bt601_625 => Some([(0.640, 0.330), (0.290, 0.600), (0.150, 0.060), D65]),
+            Self::Bt601_525 => Some([(0.630, 0.340), (0.310, 0.595), (0.155, 0.070), D65]),
+            Self::Bt2020 => Some([(0.708, 0.292), (0.170, 0.797), (0.131, 0.046), D65]),
+            Self::DciP3 => Some([
+                (0.680, 0.320),
+                (0.265, 0.690),
+                (0.150, 0.060),
+                (0.314, 0.351),
+            ]),
+            Self::DisplayP3 => Some([(0.680, 0.320), (0.265, 0.690), (0.150, 0.060), D65]),
+            Self::Unspecified | Self::Other(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransferCharacteristics {
+    /// BT.709, also used for BT.601 and BT.2020
+    Bt709,
+    Unspecified,
+    Gamma22,
+    Gamma28,
+    Bt601,
+    Linear,
+    Srgb,
+    Bt2020TenBit,
+    Bt2020TwelveBit,
+    /// SMPTE ST 2084
+    Pq,
+    /// ARIB STD-B67
+    Hlg,
+    Other(u8),
+}
+
+impl TransferCharacteristics {
+    pub const fn from_code(code: u8) -> Self {
+        match code {
+            1 => Self::Bt709,
+            2 => Self::Unspecified,
+            4 => Self::Gamma22,
+            5 => Self::Gamma28,
+            6 => Self::Bt601,
+            8 => Self::Linear,
+            13 => Self::Srgb,
+            14 => Self::Bt2020TenBit,
+            15 => Self::Bt2020TwelveBit,
+            16 => Self::Pq,
+            18 => Self::Hlg,
+            code => Self::Other(code),
+        }
+    }
+
+    pub const fn code(self) -> u8 {
+        match self {
+            Self::Bt709 => 1,
+            Self::Unspecified => 2,
+            Self::Gamma22 => 4,
+            Self::Gamma28 => 5,
+            Self::Bt601 => 6,
+            Self::Linear => 8,
+            Self::Srgb => 13,
+            Self::Bt2020TenBit => 14,
+            Self::Bt2020TwelveBit => 15,
+            Self::Pq => 16,
+            Self::Hlg => 18,
+            Self::Other(code) => code,
+        }
+    }
+
+    /// Transfer functions that can exceed SDR white
+    pub const fn is_hdr(self) -> bool {
+        matches!(self, Self::Linear | Self::Pq | Self::Hlg)
+    }
+}
+
 #[derive(Deserialize, Serialize, Type, Debug)]
 pub enum Texture {
     MemFd(zvariant::OwnedFd),
@@ -265,6 +734,9 @@ impl Communication {
 
         let instruction_handler = DecodingInstruction {
             decoder: Mutex::new(Box::new(decoder)),
+            orientation: Mutex::new(None),
+            gain_map: Mutex::new(None),
+            auxiliary_kinds: Mutex::new(Vec::new()),
         };
         let dbus_connection = zbus::ConnectionBuilder::unix_stream(unix_stream)
             .p2p()
@@ -285,10 +757,32 @@ pub trait Decoder: Send {
     fn init(&self, stream: UnixStream, details: DecodingDetails)
         -> Result<ImageInfo, DecoderError>;
     fn decode_frame(&self, frame_request: FrameRequest) -> Result<Frame, DecoderError>;
+    /// Decodes the largest embedded thumbnail
+    ///
+    /// Only called if [`ImageInfo::thumbnail_size`] is set.
+    fn decode_thumbnail(&self, _frame_request: FrameRequest) -> Result<Frame, DecoderError> {
+        Err(DecoderError::DecodingError(String::from(
+            "Image has no embedded thumbnail",
+        )))
+    }
 }
 
 struct DecodingInstruction {
     decoder: Mutex<Box<dyn Decoder>>,
+    /// EXIF orientation applied to all frames
+    orientation: Mutex<Option<u16>>,
+    gain_map: Mutex<Option<GainMap>>,
+    auxiliary_kinds: Mutex<Vec<AuxiliaryImageKind>>,
+}
+
+/// Gain map used for HDR reconstruction
+#[derive(Clone, Copy)]
+struct GainMap {
+    /// Index in [`ImageInfo::auxiliary_images`]
+    index: u32,
+    info: GainMapInfo,
+    /// Size of the image as reported by the loader
+    image_size: (u32, u32),
 }
 
 #[zbus::dbus_interface(name = "org.gnome.glycin.DecodingInstruction")]
@@ -296,25 +790,305 @@ impl DecodingInstruction {
     async fn init(&self, message: DecodingRequest) -> Result<ImageInfo, RemoteError> {
         let fd = message.fd.into_raw_fd();
         let stream = unsafe { UnixStream::from_raw_fd(fd) };
+        let apply_transformations = message.details.apply_transformations;
+        let languages = message.details.languages.clone();
 
-        let image_info = self
+        let mut image_info = self
             .decoder
             .lock()
             .or(Err(RemoteError::InternalDecoderError))?
             .init(stream, message.details)?;
 
+        image_info.exif_info = image_info
+            .exif
+            .as_ref()
+            .and_then(|exif| ExifInfo::parse(exif))
+            .into();
+        image_info.iptc_info = image_info
+            .iptc
+            .as_ref()
+            .and_then(|iptc| IptcInfo::parse(iptc))
+            .into();
+
+        if image_info.dimensions_inch.is_none() {
+            let resolution = image_info
+                .exif_info
+                .as_ref()
+                .and_then(|exif_info| exif_info.resolution.as_ref().copied());
+            if let Some((x_ppi, y_ppi)) = resolution {
+                image_info.set_resolution(x_ppi, y_ppi);
+            }
+        }
+
+        localization::fill_title_description(&mut image_info, &languages);
+
+        let gain_map = image_info.auxiliary_images.as_ref().and_then(|images| {
+            images.iter().zip(0..).find_map(|(image, index)| {
+                Some(GainMap {
+                    index,
+                    info: *image.gain_map.as_ref()?,
+                    image_size: (image_info.width, image_info.height),
+                })
+            })
+        });
+        *self
+            .gain_map
+            .lock()
+            .or(Err(RemoteError::InternalDecoderError))? = gain_map;
+        *self
+            .auxiliary_kinds
+            .lock()
+            .or(Err(RemoteError::InternalDecoderError))? = image_info
+            .auxiliary_images
+            .iter()
+            .flatten()
+            .map(|image| image.kind)
+            .collect();
+
+        // Loaders that don't handle orientation themselves leave it to the EXIF data
+        if apply_transformations && !image_info.transformations_applied {
+            let orientation = image_info
+                .exif_info
+                .as_ref()
+                .and_then(|exif_info| exif_info.orientation.as_ref().copied())
+                .filter(|orientation| (2..=8).contains(orientation));
+
+            if orientation.is_some_and(orientation::swaps_dimensions) {
+                std::mem::swap(&mut image_info.width, &mut image_info.height);
+                image_info.dimensions_inch = image_info
+                    .dimensions_inch
+                    .as_ref()
+                    .map(|(width, height)| (*height, *width))
+                    .into();
+                image_info.thumbnail_size = image_info
+                    .thumbnail_size
+                    .as_ref()
+                    .map(|(width, height)| (*height, *width))
+                    .into();
+                // The orientation is also applied to auxiliary images
+                for auxiliary_image in image_info.auxiliary_images.iter_mut().flatten() {
+                    std::mem::swap(&mut auxiliary_image.width, &mut auxiliary_image.height);
+                }
+            }
+
+            *self
+                .orientation
+                .lock()
+                .or(Err(RemoteError::InternalDecoderError))? = orientation;
+            image_info.transformations_applied = true;
+        }
+
         Ok(image_info)
     }
 
     async fn decode_frame(&self, frame_request: FrameRequest) -> Result<Frame, RemoteError> {
-        self.decoder
+        if let Some(index) = frame_request.auxiliary_image.as_ref() {
+            let n_auxiliary_images = self
+                .auxiliary_kinds
+                .lock()
+                .or(Err(RemoteError::InternalDecoderError))?
+                .len();
+            // Loaders without auxiliary images would return the primary image
+            if index.try_usize().map_err(DecoderError::from)? >= n_auxiliary_images {
+                return Err(RemoteError::DecodingError(format!(
+                    "Auxiliary image {index} does not exist"
+                )));
+            }
+        }
+
+        let decoder = self
+            .decoder
+            .lock()
+            .or(Err(RemoteError::InternalDecoderError))?;
+        let mut frame = decoder.decode_frame(frame_request.clone())?;
+
+        let gain_map = *self
+            .gain_map
+            .lock()
+            .or(Err(RemoteError::InternalDecoderError))?;
+        if let (Some(headroom), Some(gain_map), None) = (
+            frame_request.hdr_headroom.as_ref(),
+            gain_map,
+            frame_request.auxiliary_image.as_ref(),
+        ) {
+            let gain_map_frame = decoder.decode_frame(FrameRequest {
+                auxiliary_image: Some(gain_map.index).into(),
+                ..Default::default()
+            })?;
+
+            match reconstruct_hdr(
+                &mut frame,
+                &gain_map_frame,
+                &gain_map,
+                *headroom,
+                &frame_request,
+            ) {
+                Ok(hdr_frame) => frame = hdr_frame,
+                Err(err) => eprintln!("Failed to apply gain map: {err}"),
+            }
+        }
+
+        self.finish_frame(frame, &frame_request)
+    }
+
+    async fn decode_thumbnail(&self, frame_request: FrameRequest) -> Result<Frame, RemoteError> {
+        let frame = self
+            .decoder
             .lock()
             .or(Err(RemoteError::InternalDecoderError))?
-            .decode_frame(frame_request)
-            .map_err(Into::into)
+            .decode_thumbnail(frame_request.clone())?;
+
+        self.finish_frame(frame, &frame_request)
     }
 }
 
+impl DecodingInstruction {
+    /// Converts colors and applies the orientation
+    fn finish_frame(
+        &self,
+        mut frame: Frame,
+        frame_request: &FrameRequest,
+    ) -> Result<Frame, RemoteError> {
+        let has_colors = match frame_request.auxiliary_image.as_ref() {
+            Some(index) => self
+                .auxiliary_kinds
+                .lock()
+                .or(Err(RemoteError::InternalDecoderError))?
+                .get(index.try_usize().map_err(DecoderError::from)?)
+                .is_some_and(|kind| kind.has_colors()),
+            None => true,
+        };
+
+        // Auxiliary images like depth maps contain data instead of colors
+        if has_colors {
+            // Untrusted color profiles are only parsed inside the sandbox
+            if let Err(err) = convert_colors(&mut frame, frame_request) {
+                eprintln!("Failed to convert colors: {err}");
+            }
+        }
+
+        let orientation = *self
+            .orientation
+            .lock()
+            .or(Err(RemoteError::InternalDecoderError))?;
+        if let Some(orientation) = orientation {
+            orientation::apply_orientation(&mut frame, orientation).map_err(DecoderError::from)?;
+        }
+
+        Ok(frame)
+    }
+}
+
+/// Applies the gain map to the base image
+fn reconstruct_hdr(
+    base: &mut Frame,
+    gain_map_frame: &Frame,
+    gain_map: &GainMap,
+    headroom: f32,
+    frame_request: &FrameRequest,
+) -> anyhow::Result<Frame> {
+    // The gain is applied in linear light, without clipping the gamut to sRGB first
+    if let Some(iccp) = base.iccp.as_ref().cloned() {
+        let gray = base.memory_format.n_channels() <= 2;
+        let cicp = icc::gamut_cicp(&iccp, gray)?;
+        let color_conversion = ColorConversion {
+            target: ColorTarget::Icc,
+            target_icc: icc::cicp_icc(&cicp, gray)?,
+            rendering_intent: Some(RenderingIntent::RelativeColorimetric).into(),
+            black_point_compensation: false,
+        };
+        convert_colors(
+            base,
+            &FrameRequest {
+                color_conversion,
+                ..Default::default()
+            },
+        )?;
+        anyhow::ensure!(base.iccp.is_none(), "Failed to convert base image");
+        base.cicp = Some(cicp).into();
+    }
+
+    // Only a frame with the size of the clip area was clipped by the loader
+    let (total_width, total_height) = frame_request
+        .scale
+        .as_ref()
+        .copied()
+        .unwrap_or(gain_map.image_size);
+    let region = match frame_request.clip.as_ref() {
+        Some((x, y, clip_width, clip_height))
+            if (base.width, base.height) == (*clip_width, *clip_height)
+                && (base.width, base.height) != (total_width, total_height) =>
+        {
+            let relative = |value: u32, total: u32| value as f32 / total.max(1) as f32;
+            [
+                relative(*x, total_width),
+                relative(*y, total_height),
+                relative(*clip_width, total_width),
+                relative(*clip_height, total_height),
+            ]
+        }
+        _ => [0., 0., 1., 1.],
+    };
+
+    gain_map::apply_gain_map(base, gain_map_frame, &gain_map.info, headroom, region)
+}
+
+/// Applies color conversion and tone mapping to the frame's texture
+///
+/// Color information is removed from the frame if the data was converted.
+fn convert_colors(frame: &mut Frame, frame_request: &FrameRequest) -> anyhow::Result<()> {
+    let Texture::MemFd(fd) = &frame.texture;
+    let mut mmap = unsafe { memmap::MmapMut::map_mut(fd.as_raw_fd()) }?;
+
+    let n_bytes = frame.memory_format.n_bytes().usize();
+    let width = frame.width.try_usize()? * n_bytes;
+    let stride = frame.stride.try_usize()?;
+    let height = frame.height.try_usize()?;
+
+    if stride < width || mmap.len() < stride * height {
+        anyhow::bail!("Texture is smaller than announced: {frame:?}");
+    }
+
+    // Conversions work on whole pixels, so rows must be aligned with the pixel size
+    if stride % n_bytes != 0 {
+        for row in 1..height {
+            mmap.copy_within(row * stride..row * stride + width, row * width);
+        }
+        frame.stride = width.try_u32()?;
+    }
+
+    let buf = &mut mmap[..frame.stride.try_usize()? * height];
+    let color_conversion = &frame_request.color_conversion;
+
+    let mut transformed = match icc::apply_transformation(frame, buf, color_conversion) {
+        Ok(transformed) => transformed,
+        Err(err) => {
+            eprintln!("Failed to apply ICC profile: {err}");
+            false
+        }
+    };
+
+    if let Some(tone_mapping) = frame_request.tone_mapping.as_ref() {
+        match tone_mapping::apply_tone_mapping(frame, buf, *tone_mapping) {
+            Ok(true) => {
+                transformed = true;
+                if let Err(err) = icc::apply_srgb_to_target(frame, buf, color_conversion) {
+                    eprintln!("Failed to apply target color profile: {err}");
+                }
+            }
+            Ok(false) => {}
+            Err(err) => eprintln!("Failed to apply tone mapping: {err}"),
+        }
+    }
+
+    if transformed {
+        frame.iccp = None.into();
+        frame.cicp = None.into();
+    }
+
+    Ok(())
+}
+
 #[derive(zbus::DBusError, Debug, Clone)]
 #[dbus_error(prefix = "org.gnome.glycin.Error")]
 pub enum RemoteError {
@@ -451,3 +1225,11 @@ pub trait SafeConversion:
 impl SafeConversion for usize {}
 impl SafeConversion for u32 {}
 impl SafeConversion for i32 {}
+
+#[test]
+fn cicp_code_test() {
+    for code in 0..=u8::MAX {
+        assert_eq!(ColorPrimaries::from_code(code).code(), code);
+        assert_eq!(TransferCharacteristics::from_code(code).code(), code);
+    }
+}
@@ -0,0 +1,118 @@
+//! Selection of localized metadata
+
+use crate::xml::xmp_lang_alt;
+use crate::ImageInfo;
+
+/// Picks the alternative matching the preferred languages
+///
+/// Alternatives are pairs of language tag and text. If no language matches,
+/// the `x-default` or untagged alternative is used, otherwise the first one.
+pub fn localized_text<'a>(
+    alternatives: impl IntoIterator<Item = (&'a str, &'a str)>,
+    languages: &[String],
+) -> Option<&'a str> {
+    let alternatives: Vec<_> = alternatives
+        .into_iter()
+        .filter(|(_, text)| !text.trim().is_empty())
+        .map(|(lang, text)| (normalize(lang), text))
+        .collect();
+
+    // Either tag can be more specific, like `de` and `de-DE`
+    let is_match = |a: &str, b: &str| {
+        a == b
+            || a.strip_prefix(b).is_some_and(|x| x.starts_with('-'))
+            || b.strip_prefix(a).is_some_and(|x| x.starts_with('-'))
+    };
+
+    let matching = languages.iter().map(|x| normalize(x)).find_map(|language| {
+        alternatives
+            .iter()
+            .find(|(lang, _)| !language.is_empty() && !lang.is_empty() && is_match(lang, &language))
+    });
+
+    matching
+        .or_else(|| {
+            alternatives
+                .iter()
+                .find(|(lang, _)| lang.is_empty() || lang == "x-default")
+        })
+        .or(alternatives.first())
+        .map(|(_, text)| text.trim())
+}
+
+/// Fills title and description from XMP, IPTC, or EXIF if not set by the loader
+pub(crate) fn fill_title_description(image_info: &mut ImageInfo, languages: &[String]) {
+    let (xmp_title, xmp_description) = image_info
+        .xmp
+        .as_deref()
+        .map(|xmp| xmp_title_description(xmp, languages))
+        .unwrap_or_default();
+    let iptc_info = image_info.iptc_info.as_ref();
+    let exif_info = image_info.exif_info.as_ref();
+
+    let title = image_info
+        .title
+        .as_ref()
+        .cloned()
+        .or(xmp_title)
+        .or_else(|| iptc_info.and_then(|x| x.object_name.as_ref().cloned()));
+
+    let description = image_info
+        .description
+        .as_ref()
+        .cloned()
+        .or(xmp_description)
+        .or_else(|| iptc_info.and_then(|x| x.caption.as_ref().cloned()))
+        .or_else(|| exif_info.and_then(|x| x.image_description.as_ref().cloned()));
+
+    image_info.title = title.into();
+    image_info.description = description.into();
+}
+
+/// Localized title and description from XMP
+pub fn xmp_title_description(xmp: &[u8], languages: &[String]) -> (Option<String>, Option<String>) {
+    let xmp_text = |property| {
+        let alternatives = xmp_lang_alt(xmp, property);
+        localized_text(
+            alternatives.iter().map(|(a, b)| (a.as_str(), b.as_str())),
+            languages,
+        )
+        .map(ToString::to_string)
+    };
+
+    let title = xmp_text("dc:title");
+    let description =
+        xmp_text("Iptc4xmpCore:AltTextAccessibility").or_else(|| xmp_text("dc:description"));
+
+    (title, description)
+}
+
+/// Converts POSIX locales like `de_DE.UTF-8` to language tags like `de-de`
+fn normalize(language: &str) -> String {
+    let language = language.split(['.', '@']).next().unwrap_or_default();
+
+    if language == "C" || language == "POSIX" {
+        String::new()
+    } else {
+        language.replace('_', "-").to_lowercase()
+    }
+}
+
+#[test]
+fn localized_text_test() {
+    let alternatives = [("x-default", "Cat"), ("de-DE", "Katze"), ("fr", "Chat")];
+    let languages = |x: &[&str]| x.iter().map(ToString::to_string).collect::<Vec<_>>();
+
+    assert_eq!(
+        localized_text(alternatives, &languages(&["de_AT.UTF-8", "de", "C"])),
+        Some("Katze")
+    );
+    assert_eq!(
+        localized_text(alternatives, &languages(&["fr_FR", "fr"])),
+        Some("Chat")
+    );
+    assert_eq!(
+        localized_text(alternatives, &languages(&["C"])),
+        Some("Cat")
+    );
+}
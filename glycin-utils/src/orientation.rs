@@ -0,0 +1,60 @@
+//! EXIF orientation
+
+use crate::{Frame, SafeConversion, SharedMemory, Texture};
+
+use std::os::fd::AsRawFd;
+
+/// Orientations that swap width and height
+pub const fn swaps_dimensions(orientation: u16) -> bool {
+    orientation >= 5
+}
+
+/// Rotates and mirrors the frame according to the EXIF orientation
+pub fn apply_orientation(frame: &mut Frame, orientation: u16) -> anyhow::Result<()> {
+    let Texture::MemFd(fd) = &frame.texture;
+    let mmap = unsafe { memmap::MmapMut::map_mut(fd.as_raw_fd()) }?;
+
+    let n_bytes = frame.memory_format.n_bytes().usize();
+    let width = frame.width.try_usize()?;
+    let height = frame.height.try_usize()?;
+    let stride = frame.stride.try_usize()?;
+
+    if stride < width * n_bytes || mmap.len() < stride * height {
+        anyhow::bail!("Texture is smaller than announced: {frame:?}");
+    }
+
+    let (out_width, out_height) = if swaps_dimensions(orientation) {
+        (height, width)
+    } else {
+        (width, height)
+    };
+    let out_stride = out_width * n_bytes;
+
+    let mut memory = SharedMemory::new((out_stride * out_height).try_u64()?);
+
+    for y in 0..height {
+        for x in 0..width {
+            let (out_x, out_y) = match orientation {
+                2 => (width - 1 - x, y),
+                3 => (width - 1 - x, height - 1 - y),
+                4 => (x, height - 1 - y),
+                5 => (y, x),
+                6 => (height - 1 - y, x),
+                7 => (height - 1 - y, width - 1 - x),
+                8 => (y, width - 1 - x),
+                _ => (x, y),
+            };
+
+            let pos = y * stride + x * n_bytes;
+            let out_pos = out_y * out_stride + out_x * n_bytes;
+            memory[out_pos..out_pos + n_bytes].copy_from_slice(&mmap[pos..pos + n_bytes]);
+        }
+    }
+
+    frame.width = out_width.try_u32()?;
+    frame.height = out_height.try_u32()?;
+    frame.stride = out_stride.try_u32()?;
+    frame.texture = memory.into_texture();
+
+    Ok(())
+}
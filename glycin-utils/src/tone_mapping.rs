@@ -0,0 +1,339 @@
+//! Mapping of HDR content into SDR range
+
+use crate::{
+    ColorPrimaries, Frame, MemoryFormat, ToneMapping, ToneMappingOperator, TransferCharacteristics,
+};
+use half::f16;
+
+/// Luminance of SDR reference white in nits, see ITU-R BT.2408
+const REFERENCE_WHITE: f32 = 203.;
+
+/// Maps HDR pixel values into SDR range
+///
+/// Only frames that signal a linear, PQ, or HLG transfer via CICP are
+/// changed. The result is sRGB encoded and stored in the frame's memory format.
+/// Returns `false` if the frame was left untouched.
+pub fn apply_tone_mapping(
+    frame: &Frame,
+    mmap: &mut [u8],
+    tone_mapping: ToneMapping,
+) -> anyhow::Result<bool> {
+    let Some(cicp) = frame.cicp.as_ref().filter(|_| frame.iccp.is_none()) else {
+        return Ok(false);
+    };
+
+    let to_linear: fn(f32) -> f32 = match cicp.transfer() {
+        TransferCharacteristics::Linear => |x| x,
+        TransferCharacteristics::Pq => pq_to_linear,
+        TransferCharacteristics::Hlg => hlg_to_linear,
+        _ => return Ok(false),
+    };
+
+    let layout = PixelLayout::new(frame.memory_format);
+    let primaries = if layout.color[0] == layout.color[1] {
+        None
+    } else {
+        primaries_to_srgb(cicp.primaries())
+    };
+    let n_bytes = frame.memory_format.n_bytes().usize();
+    let width = usize::try_from(frame.width)?;
+    let stride = usize::try_from(frame.stride)?;
+    let exposure = f32::powf(2., tone_mapping.exposure);
+
+    for row in mmap
+        .chunks_exact_mut(stride)
+        .take(usize::try_from(frame.height)?)
+    {
+        for pixel in row[..width * n_bytes].chunks_exact_mut(n_bytes) {
+            let alpha = layout.alpha.map(|i| layout.read(pixel, i));
+            let premultiplied_alpha = alpha.filter(|a| layout.premultiplied && *a > 0.);
+
+            let mut rgb = layout.color.map(|i| {
+                let value = layout.read(pixel, i);
+                let value = premultiplied_alpha.map_or(value, |a| value / a);
+                to_linear(value) * exposure
+            });
+
+            if let Some(matrix) = &primaries {
+                rgb = mul(matrix, rgb);
+            }
+
+            tone_mapping.operator.apply(&mut rgb);
+
+            for (i, value) in layout.color.into_iter().zip(rgb) {
+                let value = linear_to_srgb(value.clamp(0., 1.));
+                let value = premultiplied_alpha.map_or(value, |a| value * a);
+                layout.write(pixel, i, value);
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+type Matrix = [[f32; 3]; 3];
+
+/// Matrix converting linear RGB with the given primaries to linear sRGB
+fn primaries_to_srgb(primaries: ColorPrimaries) -> Option<Matrix> {
+    if matches!(
+        primaries,
+        ColorPrimaries::Srgb | ColorPrimaries::Unspecified
+    ) {
+        return None;
+    }
+
+    let src = rgb_to_xyz(primaries.chromaticities()?);
+    let target = rgb_to_xyz(ColorPrimaries::Srgb.chromaticities()?);
+
+    Some(mul_matrix(&invert(&target)?, &src))
+}
+
+/// Matrix from linear RGB to XYZ, given the xy chromaticities of the primaries and white point
+fn rgb_to_xyz(chromaticities: [(f64, f64); 4]) -> Matrix {
+    let xyz = |(x, y): (f64, f64)| [(x / y) as f32, 1., ((1. - x - y) / y) as f32];
+
+    let [r, g, b, w] = chromaticities.map(xyz);
+    let primaries = [[r[0], g[0], b[0]], [r[1], g[1], b[1]], [r[2], g[2], b[2]]];
+    // Scale primaries such that RGB 1, 1, 1 results in the white point
+    let scale = invert(&primaries).map_or([1.; 3], |x| mul(&x, w));
+
+    primaries.map(|row| [row[0] * scale[0], row[1] * scale[1], row[2] * scale[2]])
+}
+
+fn mul(m: &Matrix, v: [f32; 3]) -> [f32; 3] {
+    m.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+fn mul_matrix(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut result = [[0.; 3]; 3];
+    for (i, row) in result.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    result
+}
+
+fn invert(m: &Matrix) -> Option<Matrix> {
+    let [[a, b, c], [d, e, f], [g, h, i]] = *m;
+
+    let det = a * (e * i - f * h) - b * (d * i - f * g) + c * (d * h - e * g);
+    if det == 0. {
+        return None;
+    }
+
+    Some(
+        [
+            [e * i - f * h, c * h - b * i, b * f - c * e],
+            [f * g - d * i, a * i - c * g, c * d - a * f],
+            [d * h - e * g, b * g - a * h, a * e - b * d],
+        ]
+        .map(|row| row.map(|x| x / det)),
+    )
+}
+
+impl ToneMappingOperator {
+    fn apply(self, rgb: &mut [f32; 3]) {
+        match self {
+            Self::Clip => {}
+            Self::Reinhard => {
+                // Applied to the luminance to preserve hue and saturation
+                let luminance = 0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2];
+                if luminance > 0. {
+                    let scale = 1. / (1. + luminance);
+                    rgb.iter_mut().for_each(|x| *x *= scale);
+                }
+            }
+            Self::Hable => {
+                const WHITE_POINT: f32 = 11.2;
+                const EXPOSURE_BIAS: f32 = 2.;
+                let white_scale = 1. / hable(WHITE_POINT);
+                rgb.iter_mut()
+                    .for_each(|x| *x = hable(*x * EXPOSURE_BIAS) * white_scale);
+            }
+            Self::Aces => {
+                rgb.iter_mut().for_each(|x| *x = aces(*x));
+            }
+        }
+    }
+}
+
+/// Filmic curve from Uncharted 2 by John Hable
+fn hable(x: f32) -> f32 {
+    const A: f32 = 0.15;
+    const B: f32 = 0.50;
+    const C: f32 = 0.10;
+    const D: f32 = 0.20;
+    const E: f32 = 0.02;
+    const F: f32 = 0.30;
+
+    ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F
+}
+
+/// ACES filmic curve fit by Krzysztof Narkowicz
+fn aces(x: f32) -> f32 {
+    let x = x * 0.6;
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+/// PQ (SMPTE ST 2084) EOTF, relative to reference white
+pub(crate) fn pq_to_linear(x: f32) -> f32 {
+    const M1: f32 = 2610. / 16384.;
+    const M2: f32 = 2523. / 4096. * 128.;
+    const C1: f32 = 3424. / 4096.;
+    const C2: f32 = 2413. / 4096. * 32.;
+    const C3: f32 = 2392. / 4096. * 32.;
+
+    let p = x.max(0.).powf(1. / M2);
+    let nits = 10_000. * ((p - C1).max(0.) / (C2 - C3 * p)).powf(1. / M1);
+
+    nits / REFERENCE_WHITE
+}
+
+/// HLG (ITU-R BT.2100) inverse OETF and OOTF for a 1000 nits display, relative to reference white
+pub(crate) fn hlg_to_linear(x: f32) -> f32 {
+    const A: f32 = 0.178_832_77;
+    const B: f32 = 0.284_668_92;
+    const C: f32 = 0.559_910_7;
+    const PEAK: f32 = 1000.;
+    const GAMMA: f32 = 1.2;
+
+    let x = x.max(0.);
+    let scene = if x <= 0.5 {
+        x * x / 3.
+    } else {
+        (((x - C) / A).exp() + B) / 12.
+    };
+
+    // OOTF applied per channel as an approximation
+    PEAK * scene.powf(GAMMA) / REFERENCE_WHITE
+}
+
+fn linear_to_srgb(x: f32) -> f32 {
+    if x <= 0.003_130_8 {
+        x * 12.92
+    } else {
+        1.055 * x.powf(1. / 2.4) - 0.055
+    }
+}
+
+pub(crate) fn srgb_to_linear(x: f32) -> f32 {
+    if x <= 0.040_45 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum ChannelType {
+    U8,
+    U16,
+    F16,
+    F32,
+}
+
+/// Position and type of the channels within a pixel
+pub(crate) struct PixelLayout {
+    channel_type: ChannelType,
+    /// Indices of red, green, and blue, all the same for grayscale
+    pub(crate) color: [usize; 3],
+    pub(crate) alpha: Option<usize>,
+    pub(crate) premultiplied: bool,
+}
+
+impl PixelLayout {
+    pub(crate) const fn new(format: MemoryFormat) -> Self {
+        use ChannelType::*;
+
+        let (channel_type, color, alpha, premultiplied) = match format {
+            MemoryFormat::B8g8r8a8Premultiplied => (U8, [2, 1, 0], Some(3), true),
+            MemoryFormat::A8r8g8b8Premultiplied => (U8, [1, 2, 3], Some(0), true),
+            MemoryFormat::R8g8b8a8Premultiplied => (U8, [0, 1, 2], Some(3), true),
+            MemoryFormat::B8g8r8a8 => (U8, [2, 1, 0], Some(3), false),
+            MemoryFormat::A8r8g8b8 => (U8, [1, 2, 3], Some(0), false),
+            MemoryFormat::R8g8b8a8 => (U8, [0, 1, 2], Some(3), false),
+            MemoryFormat::A8b8g8r8 => (U8, [3, 2, 1], Some(0), false),
+            MemoryFormat::R8g8b8 => (U8, [0, 1, 2], None, false),
+            MemoryFormat::B8g8r8 => (U8, [2, 1, 0], None, false),
+            MemoryFormat::R16g16b16 => (U16, [0, 1, 2], None, false),
+            MemoryFormat::R16g16b16a16Premultiplied => (U16, [0, 1, 2], Some(3), true),
+            MemoryFormat::R16g16b16a16 => (U16, [0, 1, 2], Some(3), false),
+            MemoryFormat::R16g16b16Float => (F16, [0, 1, 2], None, false),
+            MemoryFormat::R16g16b16a16Float => (F16, [0, 1, 2], Some(3), false),
+            MemoryFormat::R32g32b32Float => (F32, [0, 1, 2], None, false),
+            MemoryFormat::R32g32b32a32FloatPremultiplied => (F32, [0, 1, 2], Some(3), true),
+            MemoryFormat::R32g32b32a32Float => (F32, [0, 1, 2], Some(3), false),
+            MemoryFormat::G8a8 => (U8, [0, 0, 0], Some(1), false),
+            MemoryFormat::G8 => (U8, [0, 0, 0], None, false),
+            MemoryFormat::G16a16 => (U16, [0, 0, 0], Some(1), false),
+            MemoryFormat::G16 => (U16, [0, 0, 0], None, false),
+        };
+
+        Self {
+            channel_type,
+            color,
+            alpha,
+            premultiplied,
+        }
+    }
+
+    pub(crate) fn read(&self, pixel: &[u8], channel: usize) -> f32 {
+        match self.channel_type {
+            ChannelType::U8 => f32::from(pixel[channel]) / f32::from(u8::MAX),
+            ChannelType::U16 => {
+                f32::from(u16::from_ne_bytes(self.bytes(pixel, channel))) / f32::from(u16::MAX)
+            }
+            ChannelType::F16 => f16::from_ne_bytes(self.bytes(pixel, channel)).to_f32(),
+            ChannelType::F32 => f32::from_ne_bytes(self.bytes(pixel, channel)),
+        }
+    }
+
+    pub(crate) fn write(&self, pixel: &mut [u8], channel: usize, value: f32) {
+        match self.channel_type {
+            ChannelType::U8 => pixel[channel] = (value * f32::from(u8::MAX)).round() as u8,
+            ChannelType::U16 => {
+                let value = (value * f32::from(u16::MAX)).round() as u16;
+                self.bytes_mut(pixel, channel)
+                    .copy_from_slice(&value.to_ne_bytes());
+            }
+            ChannelType::F16 => self
+                .bytes_mut(pixel, channel)
+                .copy_from_slice(&f16::from_f32(value).to_ne_bytes()),
+            ChannelType::F32 => self
+                .bytes_mut(pixel, channel)
+                .copy_from_slice(&value.to_ne_bytes()),
+        }
+    }
+
+    fn bytes<const N: usize>(&self, pixel: &[u8], channel: usize) -> [u8; N] {
+        pixel[channel * N..(channel + 1) * N].try_into().unwrap()
+    }
+
+    fn bytes_mut<'a>(&self, pixel: &'a mut [u8], channel: usize) -> &'a mut [u8] {
+        let n = self.channel_size();
+        &mut pixel[channel * n..(channel + 1) * n]
+    }
+
+    const fn channel_size(&self) -> usize {
+        match self.channel_type {
+            ChannelType::U8 => 1,
+            ChannelType::U16 | ChannelType::F16 => 2,
+            ChannelType::F32 => 4,
+        }
+    }
+}
+
+#[test]
+fn bt2020_white_test() {
+    let matrix = primaries_to_srgb(ColorPrimaries::Bt2020).unwrap();
+    for value in mul(&matrix, [1., 1., 1.]) {
+        assert!((value - 1.).abs() < 0.001);
+    }
+}
+
+#[test]
+fn pq_reference_white_test() {
+    // PQ signal value of 203 nits
+    assert!((pq_to_linear(0.580_688) - 1.).abs() < 0.001);
+}
@@ -0,0 +1,188 @@
+//! Minimal XML handling for metadata
+
+/// Resolves XML entities and character references
+fn xml_unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+
+        let entity = &rest[1..end];
+        let c = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|x| u32::from_str_radix(x, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        };
+
+        if let Some(c) = c {
+            unescaped.push(c);
+            rest = &rest[end + 1..];
+        } else {
+            unescaped.push('&');
+            rest = &rest[1..];
+        }
+    }
+
+    unescaped.push_str(rest);
+    unescaped
+}
+
+/// Language alternatives of an XMP `rdf:Alt` property like `dc:title`
+pub(crate) fn xmp_lang_alt(xmp: &[u8], property: &str) -> Vec<(String, String)> {
+    let Ok(xmp) = std::str::from_utf8(xmp) else {
+        return Vec::new();
+    };
+
+    let Some(value) = element_content(xmp, property) else {
+        return Vec::new();
+    };
+
+    let mut alternatives = Vec::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("<rdf:li") {
+        rest = &rest[start..];
+        let Some(tag_end) = rest.find('>') else {
+            break;
+        };
+        let Some(end) = rest.find("</rdf:li>") else {
+            break;
+        };
+
+        if tag_end < end {
+            let lang = attribute(&rest[..tag_end], "xml:lang").unwrap_or_default();
+            let text = xml_unescape(&rest[tag_end + 1..end]);
+            alternatives.push((lang, text));
+        }
+
+        rest = &rest[end..];
+    }
+
+    alternatives
+}
+
+/// Values of an XMP property given as attribute, element, or `rdf:Seq`
+pub(crate) fn xmp_property(xmp: &str, property: &str) -> Vec<String> {
+    let pattern = format!("{property}=");
+    let mut pos = 0;
+    while let Some(found) = xmp[pos..].find(&pattern) {
+        let start = pos + found;
+        pos = start + pattern.len();
+
+        // Don't match attributes that only end with the name
+        if !xmp[..start].ends_with(char::is_whitespace) {
+            continue;
+        }
+
+        let Some(quote) = xmp[pos..]
+            .chars()
+            .next()
+            .filter(|c| matches!(c, '"' | '\''))
+        else {
+            continue;
+        };
+        if let Some(value) = xmp[pos + 1..].split(quote).next() {
+            return vec![xml_unescape(value)];
+        }
+    }
+
+    let Some(content) = element_content(xmp, property) else {
+        return Vec::new();
+    };
+
+    if !content.contains("<rdf:li") {
+        return vec![xml_unescape(content.trim())];
+    }
+
+    let mut values = Vec::new();
+    let mut rest = content;
+    while let Some(start) = rest.find("<rdf:li") {
+        rest = &rest[start..];
+        let (Some(tag_end), Some(end)) = (rest.find('>'), rest.find("</rdf:li>")) else {
+            break;
+        };
+
+        if tag_end < end {
+            values.push(xml_unescape(rest[tag_end + 1..end].trim()));
+        }
+
+        rest = &rest[end..];
+    }
+
+    values
+}
+
+/// Content of the first element with the given name
+fn element_content<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{name}");
+    let close = format!("</{name}>");
+
+    let mut pos = 0;
+    loop {
+        let start = pos + xml[pos..].find(&open)?;
+        let after_name = start + open.len();
+        pos = after_name;
+
+        // Don't match elements that only start with the name
+        if !xml[after_name..].starts_with(|c: char| c == '>' || c.is_whitespace()) {
+            continue;
+        }
+
+        let content_start = after_name + xml[after_name..].find('>')? + 1;
+        let content_end = content_start + xml[content_start..].find(&close)?;
+
+        return Some(&xml[content_start..content_end]);
+    }
+}
+
+/// Value of an attribute within a start tag
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let pos = tag.find(&format!(" {name}="))? + name.len() + 2;
+    let quote = tag[pos..].chars().next()?;
+    let value = tag[pos + 1..].split(quote).next()?;
+
+    Some(xml_unescape(value))
+}
+
+#[test]
+fn xmp_lang_alt_test() {
+    let xmp = br#"<rdf:Description><dc:title><rdf:Alt>
+        <rdf:li xml:lang="x-default">Fish &amp; Chips</rdf:li>
+        <rdf:li xml:lang='de'>Fisch</rdf:li>
+    </rdf:Alt></dc:title></rdf:Description>"#;
+
+    assert_eq!(
+        xmp_lang_alt(xmp, "dc:title"),
+        [
+            (String::from("x-default"), String::from("Fish & Chips")),
+            (String::from("de"), String::from("Fisch"))
+        ]
+    );
+}
+
+#[test]
+fn xmp_property_test() {
+    let xmp = r#"<rdf:Description
+        hdrgm:Version="1.0"
+        hdrgm:GainMapMax='2.5'>
+        <hdrgm:Gamma><rdf:Seq><rdf:li>1</rdf:li><rdf:li>0.5</rdf:li></rdf:Seq></hdrgm:Gamma>
+    </rdf:Description>"#;
+
+    assert_eq!(xmp_property(xmp, "hdrgm:Version"), ["1.0"]);
+    assert_eq!(xmp_property(xmp, "hdrgm:GainMapMax"), ["2.5"]);
+    assert_eq!(xmp_property(xmp, "hdrgm:Gamma"), ["1", "0.5"]);
+    assert!(xmp_property(xmp, "hdrgm:GainMapMin").is_empty());
+}
@@ -1,7 +1,9 @@
 use crate::config;
 use crate::dbus::*;
+use crate::sidecar;
+use futures::FutureExt;
 use gio::prelude::*;
-use glycin_utils::ImageInfo;
+use glycin_utils::{Cicp, ColorTarget, ImageInfo, RenderingIntent, ToneMapping};
 use std::sync::OnceLock;
 
 pub use crate::config::MimeType;
@@ -20,7 +22,7 @@ async fn is_flatpaked() -> bool {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum SandboxMechanism {
     Bwrap,
     FlatpakSpawn,
@@ -43,6 +45,13 @@ pub struct ImageRequest {
     file: gio::File,
     cancellable: gio::Cancellable,
     sandbox_mechanism: Option<SandboxMechanism>,
+    pub(crate) tone_mapping: Option<ToneMapping>,
+    pub(crate) hdr_headroom: Option<f32>,
+    pub(crate) target_color_profile: ColorProfile,
+    pub(crate) rendering_intent: Option<RenderingIntent>,
+    pub(crate) black_point_compensation: bool,
+    apply_transformations: bool,
+    xmp_sidecar: bool,
 }
 
 impl ImageRequest {
@@ -51,6 +60,13 @@ impl ImageRequest {
             file,
             cancellable: gio::Cancellable::new(),
             sandbox_mechanism: None,
+            tone_mapping: None,
+            hdr_headroom: None,
+            target_color_profile: ColorProfile::default(),
+            rendering_intent: None,
+            black_point_compensation: false,
+            apply_transformations: true,
+            xmp_sidecar: false,
         }
     }
 
@@ -59,13 +75,201 @@ impl ImageRequest {
         self
     }
 
+    /// Map HDR content into SDR range
+    ///
+    /// By default, HDR frames are returned unchanged. Float frames then
+    /// contain linear values that can exceed `1.0`.
+    pub fn tone_mapping(&mut self, tone_mapping: Option<ToneMapping>) -> &mut Self {
+        self.tone_mapping = tone_mapping;
+        self
+    }
+
+    /// Reconstruct HDR from gain maps for a display with the given headroom
+    ///
+    /// The headroom is given in stops above SDR white. Images with a gain map,
+    /// listed in [`ImageInfo::auxiliary_images`], are then returned as linear
+    /// float frames. By default, only the SDR base image is returned. The gain
+    /// map itself can be decoded via [`FrameRequest::auxiliary_image`].
+    pub fn hdr_headroom(&mut self, hdr_headroom: Option<f32>) -> &mut Self {
+        self.hdr_headroom = hdr_headroom;
+        self
+    }
+
+    /// Color profile frames are converted into
+    ///
+    /// Defaults to sRGB.
+    pub fn target_color_profile(&mut self, color_profile: ColorProfile) -> &mut Self {
+        self.target_color_profile = color_profile;
+        self
+    }
+
+    /// Rendering intent for color profile conversions
+    ///
+    /// Defaults to the intent given by the image, like in a PNG `sRGB` chunk,
+    /// or perceptual.
+    pub fn rendering_intent(&mut self, rendering_intent: RenderingIntent) -> &mut Self {
+        self.rendering_intent = Some(rendering_intent);
+        self
+    }
+
+    /// Use black point compensation for color profile conversions
+    pub fn black_point_compensation(&mut self, black_point_compensation: bool) -> &mut Self {
+        self.black_point_compensation = black_point_compensation;
+        self
+    }
+
+    /// Apply orientation stored in the image
+    ///
+    /// Enabled by default. Disabling it returns the pixels as stored.
+    /// [`ImageInfo::transformations_applied`] reports if the orientation was
+    /// applied.
+    pub fn apply_transformations(&mut self, apply_transformations: bool) -> &mut Self {
+        self.apply_transformations = apply_transformations;
+        self
+    }
+
+    /// Include XMP sidecar files in [`ImageInfo::xmp`]
+    ///
+    /// Disabled by default. For `photo.CR2`, the sidecar `photo.CR2.xmp` or
+    /// `photo.xmp` is read by the host and its descriptions are added to the
+    /// embedded XMP. Sidecar properties, including the title and description,
+    /// take precedence over embedded ones.
+    pub fn xmp_sidecar(&mut self, xmp_sidecar: bool) -> &mut Self {
+        self.xmp_sidecar = xmp_sidecar;
+        self
+    }
+
     pub fn cancellable(&mut self, cancellable: impl IsA<gio::Cancellable>) -> &mut Self {
         self.cancellable = cancellable.upcast();
         self
     }
 
+    /// Color options sent to the loader
+    pub(crate) fn color_conversion(&self) -> glycin_utils::ColorConversion {
+        let (target, target_icc) = match &self.target_color_profile {
+            ColorProfile::Srgb => (ColorTarget::Srgb, Vec::new()),
+            ColorProfile::DisplayP3 => (ColorTarget::DisplayP3, Vec::new()),
+            ColorProfile::AdobeRgb => (ColorTarget::AdobeRgb, Vec::new()),
+            ColorProfile::Icc(icc_profile) => (ColorTarget::Icc, icc_profile.clone()),
+            ColorProfile::Untransformed => (ColorTarget::Untransformed, Vec::new()),
+        };
+
+        glycin_utils::ColorConversion {
+            target,
+            target_icc,
+            rendering_intent: self.rendering_intent.into(),
+            black_point_compensation: self.black_point_compensation,
+        }
+    }
+
     pub async fn request<'a>(self) -> Result<Image<'a>> {
         let config = config::Config::cached().await;
+        let source = self.source().await?;
+        let mime_type = source.mime_type.clone();
+
+        let process = DecoderProcess::new(
+            &mime_type,
+            config,
+            source.sandbox_mechanism,
+            self.cancellable.as_ref(),
+        )
+        .await?;
+
+        let info = self.info(&process, source, false).await?;
+
+        Ok(Image {
+            process,
+            info,
+            request: self,
+            mime_type,
+        })
+    }
+
+    /// Only load the image metadata
+    ///
+    /// Loaders skip preparations for decoding frames and the loader process
+    /// ends afterwards. Use [`ImageRequest::request_infos`] for many images.
+    pub async fn request_info(self) -> Result<ImageInfo> {
+        let mut infos = Self::request_infos([self]).await;
+        infos.remove(0)
+    }
+
+    /// Only load the metadata of many images
+    ///
+    /// Images that use the same loader share a loader process. If a loader
+    /// fails for an image, a new process is used for the following images.
+    /// Cancelling the cancellable of a request only fails that request. All
+    /// processes end once the metadata of all images is loaded.
+    pub async fn request_infos(
+        requests: impl IntoIterator<Item = ImageRequest>,
+    ) -> Vec<Result<ImageInfo>> {
+        let cancellable = gio::Cancellable::new();
+        let mut processes = Vec::new();
+
+        let mut infos = Vec::new();
+        for request in requests {
+            infos.push(request.batch_info(&mut processes, &cancellable).await);
+        }
+
+        // Ends all loader processes
+        cancellable.cancel();
+
+        infos
+    }
+
+    async fn batch_info<'a>(
+        &self,
+        processes: &mut Vec<((std::path::PathBuf, SandboxMechanism), DecoderProcess<'a>)>,
+        cancellable: &gio::Cancellable,
+    ) -> Result<ImageInfo> {
+        let config = config::Config::cached().await;
+        let source = self.source().await?;
+        let key = (
+            config.get(&source.mime_type)?.exec.clone(),
+            source.sandbox_mechanism,
+        );
+
+        let pos = if let Some(pos) = processes.iter().position(|(x, _)| *x == key) {
+            pos
+        } else {
+            let process = self
+                .cancellable_future(DecoderProcess::new(
+                    &source.mime_type,
+                    config,
+                    source.sandbox_mechanism,
+                    cancellable,
+                ))
+                .await?;
+            processes.push((key, process));
+            processes.len() - 1
+        };
+
+        let info = self
+            .cancellable_future(self.info(&processes[pos].1, source, true))
+            .await;
+        if info.is_err() {
+            processes.remove(pos);
+        }
+
+        info
+    }
+
+    /// Fails with [`gio::Cancelled`] once the request's cancellable is cancelled
+    async fn cancellable_future<T>(
+        &self,
+        future: impl std::future::Future<Output = Result<T>>,
+    ) -> Result<T> {
+        futures::select! {
+            result = future.fuse() => result,
+            _result = self.cancellable.future().fuse() => {
+                Err(gio::glib::Error::from(gio::Cancelled).into())
+            }
+        }
+    }
+
+    /// Starts reading the file and determines how to load it
+    async fn source(&self) -> Result<Source> {
+        let config = config::Config::cached().await;
 
         let gfile_worker = GFileWorker::spawn(self.file.clone(), self.cancellable.clone());
         let mime_type = Self::guess_mime_type(&gfile_worker).await?;
@@ -83,22 +287,56 @@ impl ImageRequest {
             None
         };
 
-        let process = DecoderProcess::new(
-            &mime_type,
-            config,
+        Ok(Source {
+            gfile_worker,
+            mime_type,
             sandbox_mechanism,
-            self.cancellable.as_ref(),
-        )
-        .await?;
+            base_dir,
+        })
+    }
 
-        let info = process.init(gfile_worker, base_dir).await?;
+    /// Loads the metadata via the loader process
+    async fn info(
+        &self,
+        process: &DecoderProcess<'_>,
+        source: Source,
+        info_only: bool,
+    ) -> Result<ImageInfo> {
+        let mut info = process
+            .init(
+                source.gfile_worker,
+                source.mime_type,
+                source.base_dir,
+                self.apply_transformations,
+                info_only,
+            )
+            .await?;
+
+        if self.xmp_sidecar {
+            if let Some(sidecar) = sidecar::load(&self.file).await {
+                let xmp = match info.xmp.as_deref() {
+                    Some(embedded) => sidecar::merge(embedded, &sidecar),
+                    None => sidecar.clone(),
+                };
+                info.xmp = Some(xmp).into();
+
+                // Sidecar descriptions take precedence over the embedded metadata
+                let languages: Vec<String> = gio::glib::language_names()
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect();
+                let (title, description) =
+                    glycin_utils::xmp_title_description(&sidecar, &languages);
+                if title.is_some() {
+                    info.title = title.into();
+                }
+                if description.is_some() {
+                    info.description = description.into();
+                }
+            }
+        }
 
-        Ok(Image {
-            process,
-            info,
-            request: self,
-            mime_type,
-        })
+        Ok(info)
     }
 
     async fn guess_mime_type(gfile_worker: &GFileWorker) -> Result<String> {
@@ -123,6 +361,14 @@ impl ImageRequest {
     }
 }
 
+/// File being read and how to load it
+struct Source {
+    gfile_worker: GFileWorker,
+    mime_type: MimeType,
+    sandbox_mechanism: SandboxMechanism,
+    base_dir: Option<std::path::PathBuf>,
+}
+
 /// Image handle containing metadata and allowing frame requests
 #[derive(Debug)]
 pub struct Image<'a> {
@@ -135,14 +381,14 @@ pub struct Image<'a> {
 impl<'a> Image<'a> {
     pub async fn next_frame(&self) -> Result<Frame> {
         self.process
-            .decode_frame(glycin_utils::FrameRequest::default())
+            .decode_frame(glycin_utils::FrameRequest::default(), &self.request)
             .await
             .map_err(Into::into)
     }
 
     pub async fn texture(self) -> Result<gdk::Texture> {
         self.process
-            .decode_frame(glycin_utils::FrameRequest::default())
+            .decode_frame(glycin_utils::FrameRequest::default(), &self.request)
             .await
             .map(|x| x.texture)
             .map_err(Into::into)
@@ -150,7 +396,18 @@ impl<'a> Image<'a> {
 
     pub async fn specific_frame(&self, frame_request: FrameRequest) -> Result<Frame> {
         self.process
-            .decode_frame(frame_request.request)
+            .decode_frame(frame_request.request, &self.request)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Decodes the largest embedded thumbnail
+    ///
+    /// This is much faster than decoding the image itself. Only available
+    /// if [`ImageInfo::thumbnail_size`] is set.
+    pub async fn thumbnail(&self) -> Result<Frame> {
+        self.process
+            .decode_thumbnail(&self.request)
             .await
             .map_err(Into::into)
     }
@@ -181,6 +438,38 @@ impl Drop for ImageRequest {
 pub struct Frame {
     pub texture: gdk::Texture,
     pub delay: Option<std::time::Duration>,
+    /// Color signaling for frames that were not converted to sRGB
+    ///
+    /// This is the case for HDR content without tone mapping, or color spaces
+    /// that can't be converted.
+    pub cicp: Option<Cicp>,
+    /// ICC profile for frames that were not converted
+    ///
+    /// Only set if [`ColorProfile::Untransformed`] was requested.
+    pub iccp: Option<Vec<u8>>,
+}
+
+/// Color profile to convert frames into
+///
+/// Grayscale frames are always converted to gray with gamma 2.2, unless
+/// [`ColorProfile::Untransformed`] is used.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ColorProfile {
+    #[default]
+    Srgb,
+    DisplayP3,
+    AdobeRgb,
+    /// ICC profile data, for example a monitor profile
+    Icc(Vec<u8>),
+    /// Return the data as is, with the ICC profile or CICP attached to the frame
+    Untransformed,
+}
+
+impl ColorProfile {
+    /// Load an ICC profile from a file
+    pub fn icc_file(path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        std::fs::read(path).map(Self::Icc)
+    }
 }
 
 #[derive(Default, Debug)]
@@ -203,6 +492,25 @@ impl FrameRequest {
         self.request.clip = Some((x, y, width, height)).into();
         self
     }
+
+    /// Select a layer listed in [`ImageInfo::layers`]
+    ///
+    /// Instead of a layer name, the full name of a single channel, like
+    /// `depth.Z`, can be passed. The channel is then returned as grayscale.
+    pub fn layer(mut self, part: u32, name: impl ToString) -> Self {
+        self.request.layer = Some((part, name.to_string())).into();
+        self
+    }
+
+    /// Select an image listed in [`ImageInfo::auxiliary_images`]
+    ///
+    /// Colors are only converted for previews and other views of the image,
+    /// see [`has_colors`](crate::AuxiliaryImageKind::has_colors). Decoding
+    /// fails for indices that are not listed.
+    pub fn auxiliary_image(mut self, index: u32) -> Self {
+        self.request.auxiliary_image = Some(index).into();
+        self
+    }
 }
 
 /// Returns a list of mime types for the supported image formats
@@ -15,13 +15,14 @@ use zbus::zvariant;
 use std::os::fd::AsRawFd;
 use std::os::fd::FromRawFd;
 use std::os::fd::OwnedFd;
+use std::os::fd::RawFd;
 use std::sync::Arc;
+use std::sync::OnceLock;
 
 #[derive(Clone, Debug)]
 pub struct DecoderProcess<'a> {
     _dbus_connection: zbus::Connection,
     decoding_instruction: DecodingInstructionProxy<'a>,
-    mime_type: String,
 }
 
 impl<'a> DecoderProcess<'a> {
@@ -124,25 +125,32 @@ impl<'a> DecoderProcess<'a> {
         Ok(Self {
             _dbus_connection: dbus_connection,
             decoding_instruction,
-            mime_type: mime_type.to_string(),
         })
     }
 
     pub async fn init(
         &self,
         gfile_worker: GFileWorker,
+        mime_type: config::MimeType,
         base_dir: Option<std::path::PathBuf>,
+        apply_transformations: bool,
+        info_only: bool,
     ) -> Result<ImageInfo, Error> {
         let (remote_reader, writer) = std::os::unix::net::UnixStream::pair()?;
 
         gfile_worker.write_to(writer)?;
 
         let fd = unsafe { zvariant::OwnedFd::from_raw_fd(remote_reader.as_raw_fd()) };
-        let mime_type = self.mime_type.clone();
 
         let details = DecodingDetails {
             mime_type,
             base_dir: base_dir.into(),
+            apply_transformations,
+            info_only,
+            languages: glib::language_names()
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
         };
 
         let image_info = self
@@ -161,12 +169,43 @@ impl<'a> DecoderProcess<'a> {
         image_info.await.map_err(Into::into)
     }
 
-    pub async fn decode_frame(&self, frame_request: FrameRequest) -> Result<api::Frame, Error> {
-        let mut frame = self
+    pub async fn decode_frame(
+        &self,
+        mut frame_request: FrameRequest,
+        image_request: &api::ImageRequest,
+    ) -> Result<api::Frame, Error> {
+        frame_request.color_conversion = image_request.color_conversion();
+        frame_request.tone_mapping = image_request.tone_mapping.into();
+        frame_request.hdr_headroom = image_request.hdr_headroom.into();
+
+        let frame = self
             .decoding_instruction
             .decode_frame(frame_request)
             .await?;
 
+        Self::texture_frame(frame)
+    }
+
+    pub async fn decode_thumbnail(
+        &self,
+        image_request: &api::ImageRequest,
+    ) -> Result<api::Frame, Error> {
+        let frame_request = FrameRequest {
+            color_conversion: image_request.color_conversion(),
+            tone_mapping: image_request.tone_mapping.into(),
+            ..Default::default()
+        };
+
+        let frame = self
+            .decoding_instruction
+            .decode_thumbnail(frame_request)
+            .await?;
+
+        Self::texture_frame(frame)
+    }
+
+    /// Validates the frame's memory and creates a texture from it
+    fn texture_frame(mut frame: Frame) -> Result<api::Frame, Error> {
         let Texture::MemFd(fd) = &frame.texture;
         let raw_fd = fd.as_raw_fd();
         let mut mmap = unsafe { memmap::MmapMut::map_mut(raw_fd) }?;
@@ -210,9 +249,13 @@ impl<'a> DecoderProcess<'a> {
             unsafe { memmap::MmapMut::map_mut(raw_fd) }?
         };
 
-        if let Err(err) = crate::icc::apply_transformation(&frame, &mut mmap) {
-            eprintln!("Failed to apply ICC profile: {err}");
+        if !gdk_supports_gray() {
+            mmap = expand_gray(&mut frame, mmap, raw_fd)?;
         }
+
+        // The loader only leaves color information if the data wasn't converted
+        let iccp = Option::<Vec<u8>>::from(frame.iccp);
+        let cicp = frame.cicp.as_ref().copied().filter(|_| iccp.is_none());
         drop(mmap);
 
         let mfd = memfd::Memfd::try_from_fd(raw_fd).unwrap();
@@ -247,6 +290,8 @@ impl<'a> DecoderProcess<'a> {
         Ok(api::Frame {
             texture: texture.upcast(),
             delay: frame.delay.into(),
+            cicp,
+            iccp,
         })
     }
 }
@@ -261,6 +306,7 @@ const BUF_SIZE: usize = u16::MAX as usize;
 trait DecodingInstruction {
     async fn init(&self, message: DecodingRequest) -> Result<ImageInfo, RemoteError>;
     async fn decode_frame(&self, frame_request: FrameRequest) -> Result<Frame, RemoteError>;
+    async fn decode_thumbnail(&self, frame_request: FrameRequest) -> Result<Frame, RemoteError>;
 }
 
 const fn gdk_memory_format(format: MemoryFormat) -> gdk::MemoryFormat {
@@ -291,6 +337,71 @@ const fn gdk_memory_format(format: MemoryFormat) -> gdk::MemoryFormat {
     }
 }
 
+/// Checks if the running GDK knows the gray memory formats added in GTK 4.12
+fn gdk_supports_gray() -> bool {
+    static SUPPORTS_GRAY: OnceLock<bool> = OnceLock::new();
+
+    *SUPPORTS_GRAY.get_or_init(|| {
+        glib::EnumClass::with_type(gdk::MemoryFormat::static_type())
+            .is_some_and(|class| class.value(20).is_some())
+    })
+}
+
+/// Converts gray formats to RGB(A) for GDK versions that don't support them
+fn expand_gray(
+    frame: &mut Frame,
+    mmap: memmap::MmapMut,
+    raw_fd: RawFd,
+) -> Result<memmap::MmapMut, Error> {
+    let (memory_format, value_size) = match frame.memory_format {
+        MemoryFormat::G8 => (MemoryFormat::R8g8b8, 1),
+        MemoryFormat::G8a8 => (MemoryFormat::R8g8b8a8, 1),
+        MemoryFormat::G16 => (MemoryFormat::R16g16b16, 2),
+        MemoryFormat::G16a16 => (MemoryFormat::R16g16b16a16, 2),
+        _ => return Ok(mmap),
+    };
+
+    let width = frame.width.try_usize()?;
+    let height = frame.height.try_usize()?;
+    let stride = frame.stride.try_usize()?;
+    let pixel_size = frame.memory_format.n_bytes().usize();
+    let out_pixel_size = memory_format.n_bytes().usize();
+    let out_stride = width * out_pixel_size;
+
+    let mut data = vec![0; out_stride * height];
+    for (row, out_row) in mmap.chunks(stride).zip(data.chunks_exact_mut(out_stride)) {
+        for (pixel, out_pixel) in row[..width * pixel_size]
+            .chunks_exact(pixel_size)
+            .zip(out_row.chunks_exact_mut(out_pixel_size))
+        {
+            let (gray, alpha) = pixel.split_at(value_size);
+            let (rgb, out_alpha) = out_pixel.split_at_mut(value_size * 3);
+            for value in rgb.chunks_exact_mut(value_size) {
+                value.copy_from_slice(gray);
+            }
+            out_alpha.copy_from_slice(alpha);
+        }
+    }
+
+    // The mmap would have the wrong size after ftruncate
+    drop(mmap);
+    nix::unistd::ftruncate(
+        raw_fd,
+        data.len()
+            .try_into()
+            .map_err(|_| ConversionTooLargerError)?,
+    )
+    .map_err(std::io::Error::from)?;
+
+    let mut mmap = unsafe { memmap::MmapMut::map_mut(raw_fd) }?;
+    mmap.copy_from_slice(&data);
+
+    frame.memory_format = memory_format;
+    frame.stride = out_stride.try_u32()?;
+
+    Ok(mmap)
+}
+
 pub struct GFileWorker {
     file: gio::File,
     writer_send: Mutex<Option<oneshot::Sender<UnixStream>>>,
@@ -20,7 +20,11 @@ pub mod dbus;
 
 mod api;
 mod config;
-mod icc;
+mod sidecar;
 
 pub use api::*;
-pub use glycin_utils::{ImageInfo, RemoteError};
+pub use glycin_utils::{
+    AuxiliaryImage, AuxiliaryImageKind, Cicp, ColorPrimaries, ExifInfo, GainMapInfo, ImageInfo,
+    ImageLayer, IptcInfo, RemoteError, RenderingIntent, ToneMapping, ToneMappingOperator,
+    TransferCharacteristics,
+};
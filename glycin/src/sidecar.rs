@@ -0,0 +1,310 @@
+//! XMP sidecar files
+//!
+//! Sidecars are read by the host since loaders only get access to the image
+//! data itself.
+
+use gio::glib;
+use gio::prelude::*;
+
+use std::ops::Range;
+use std::path::Path;
+
+/// Sidecars larger than this are ignored
+const MAX_SIZE: i64 = 16 * 1024 * 1024;
+
+/// Loads the XMP sidecar for `file`, if one exists
+///
+/// For `photo.CR2`, `photo.CR2.xmp` is preferred over `photo.xmp`.
+pub async fn load(file: &gio::File) -> Option<Vec<u8>> {
+    let parent = file.parent()?;
+    let basename = file.basename()?;
+
+    let mut stems = vec![basename.display().to_string()];
+    if let Some(stem) = Path::new(&basename).file_stem() {
+        stems.push(stem.to_string_lossy().to_string());
+    }
+
+    for stem in stems {
+        for extension in ["xmp", "XMP"] {
+            if let Some(xmp) = load_file(&parent.child(format!("{stem}.{extension}"))).await {
+                return Some(xmp);
+            }
+        }
+    }
+
+    None
+}
+
+async fn load_file(file: &gio::File) -> Option<Vec<u8>> {
+    let info = file
+        .query_info_future(
+            "standard::type,standard::size",
+            gio::FileQueryInfoFlags::NONE,
+            glib::Priority::DEFAULT,
+        )
+        .await
+        .ok()?;
+
+    if info.file_type() != gio::FileType::Regular || info.size() > MAX_SIZE {
+        return None;
+    }
+
+    let (data, _) = file.load_contents_future().await.ok()?;
+    rdf_body(std::str::from_utf8(&data).ok()?)?;
+
+    Some(data.to_vec())
+}
+
+/// Adds the descriptions of the sidecar to the embedded XMP
+///
+/// Properties set in the sidecar replace the embedded ones. Properties are
+/// compared by their prefixed name, like `dc:title`.
+pub fn merge(embedded: &[u8], sidecar: &[u8]) -> Vec<u8> {
+    let (Ok(embedded), Ok(sidecar)) = (std::str::from_utf8(embedded), std::str::from_utf8(sidecar))
+    else {
+        return sidecar.to_vec();
+    };
+
+    let (Some(sidecar_body), Some(embedded_body)) = (rdf_body(sidecar), rdf_body_range(embedded))
+    else {
+        return sidecar.as_bytes().to_vec();
+    };
+
+    let properties = properties(sidecar_body);
+
+    let mut xmp = embedded[..embedded_body.start].to_string();
+    xmp.push_str(&without_properties(
+        &embedded[embedded_body.clone()],
+        &properties,
+    ));
+    xmp.push_str(sidecar_body);
+    xmp.push_str(&embedded[embedded_body.end..]);
+    xmp.into_bytes()
+}
+
+/// Content of the `rdf:RDF` element
+fn rdf_body(xmp: &str) -> Option<&str> {
+    rdf_body_range(xmp).map(|range| &xmp[range])
+}
+
+fn rdf_body_range(xmp: &str) -> Option<Range<usize>> {
+    let start = tags(xmp).find(|tag| tag.kind == TagKind::Start && tag.name == "rdf:RDF")?;
+    let end = start.range.end + xmp[start.range.end..].find("</rdf:RDF>")?;
+
+    Some(start.range.end..end)
+}
+
+/// Names of the properties in the `rdf:Description` elements
+///
+/// Properties can be attributes of the description or child elements.
+fn properties(rdf_body: &str) -> Vec<&str> {
+    let mut properties = Vec::new();
+    let mut depth = 0_usize;
+
+    for tag in tags(rdf_body) {
+        if tag.kind == TagKind::End {
+            depth = depth.saturating_sub(1);
+            continue;
+        }
+
+        match depth {
+            0 if tag.name == "rdf:Description" => properties.extend(
+                tag.attributes
+                    .iter()
+                    .map(|(name, _)| *name)
+                    .filter(|name| is_property(name)),
+            ),
+            1 if is_property(tag.name) => properties.push(tag.name),
+            _ => {}
+        }
+
+        if tag.kind == TagKind::Start {
+            depth += 1;
+        }
+    }
+
+    properties
+}
+
+/// Removes the given properties from the `rdf:Description` elements
+fn without_properties(rdf_body: &str, properties: &[&str]) -> String {
+    let mut removed: Vec<Range<usize>> = Vec::new();
+    let mut depth = 0_usize;
+    // Start and depth of a removed property element
+    let mut removed_element: Option<(usize, usize)> = None;
+
+    for tag in tags(rdf_body) {
+        if tag.kind == TagKind::End {
+            depth = depth.saturating_sub(1);
+            if let Some((start, element_depth)) = removed_element {
+                if depth == element_depth {
+                    removed.push(start..tag.range.end);
+                    removed_element = None;
+                }
+            }
+            continue;
+        }
+
+        if removed_element.is_none() {
+            match depth {
+                0 if tag.name == "rdf:Description" => {
+                    for (name, range) in &tag.attributes {
+                        if properties.contains(name) {
+                            // Includes the whitespace before the attribute
+                            let start = rdf_body[..range.start].trim_end().len();
+                            removed.push(start..range.end);
+                        }
+                    }
+                }
+                1 if properties.contains(&tag.name) => match tag.kind {
+                    TagKind::Empty => removed.push(tag.range.clone()),
+                    _ => removed_element = Some((tag.range.start, depth)),
+                },
+                _ => {}
+            }
+        }
+
+        if tag.kind == TagKind::Start {
+            depth += 1;
+        }
+    }
+
+    let mut result = String::with_capacity(rdf_body.len());
+    let mut pos = 0;
+    for range in removed {
+        result.push_str(&rdf_body[pos..range.start]);
+        pos = range.end;
+    }
+    result.push_str(&rdf_body[pos..]);
+
+    result
+}
+
+/// Namespace declarations and RDF syntax are not properties
+fn is_property(name: &str) -> bool {
+    !name.starts_with("xmlns") && !name.starts_with("rdf:")
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum TagKind {
+    Start,
+    /// Self-closing tag like `<a/>`
+    Empty,
+    End,
+}
+
+struct Tag<'a> {
+    name: &'a str,
+    kind: TagKind,
+    range: Range<usize>,
+    /// Names and ranges of the attributes
+    attributes: Vec<(&'a str, Range<usize>)>,
+}
+
+/// Element tags in document order
+///
+/// Comments, processing instructions, and CDATA sections are skipped. Stops
+/// at malformed tags.
+fn tags(xml: &str) -> impl Iterator<Item = Tag<'_>> {
+    let mut pos = 0;
+
+    std::iter::from_fn(move || loop {
+        let start = pos + xml[pos..].find('<')?;
+        let rest = &xml[start..];
+
+        let skip_to = [
+            ("<!--", "-->"),
+            ("<![CDATA[", "]]>"),
+            ("<?", "?>"),
+            ("<!", ">"),
+        ]
+        .into_iter()
+        .find(|(open, _)| rest.starts_with(open));
+        if let Some((_, close)) = skip_to {
+            pos = start + rest.find(close)? + close.len();
+            continue;
+        }
+
+        let tag = parse_tag(xml, start)?;
+        pos = tag.range.end;
+        return Some(tag);
+    })
+}
+
+fn parse_tag(xml: &str, start: usize) -> Option<Tag<'_>> {
+    let is_name_end = |c: char| c.is_whitespace() || matches!(c, '/' | '>' | '=');
+
+    let (kind, name_start) = if xml[start..].starts_with("</") {
+        (TagKind::End, start + 2)
+    } else {
+        (TagKind::Start, start + 1)
+    };
+    let name_end = name_start + xml[name_start..].find(is_name_end)?;
+    let name = &xml[name_start..name_end];
+    if name.is_empty() {
+        return None;
+    }
+
+    let mut attributes = Vec::new();
+    let mut pos = name_end;
+    loop {
+        pos = xml.len() - xml[pos..].trim_start().len();
+        let rest = &xml[pos..];
+
+        if rest.starts_with('>') {
+            return Some(Tag {
+                name,
+                kind,
+                range: start..pos + 1,
+                attributes,
+            });
+        } else if rest.starts_with("/>") && kind == TagKind::Start {
+            return Some(Tag {
+                name,
+                kind: TagKind::Empty,
+                range: start..pos + 2,
+                attributes,
+            });
+        }
+
+        let attribute_end = pos + rest.find(is_name_end)?;
+        let attribute = &xml[pos..attribute_end];
+        let value = xml[attribute_end..]
+            .trim_start()
+            .strip_prefix('=')?
+            .trim_start();
+        let quote = value.chars().next().filter(|c| matches!(c, '"' | '\''))?;
+        let value_start = xml.len() - value.len() + 1;
+        let value_end = value_start + xml[value_start..].find(quote)?;
+
+        if attribute.is_empty() {
+            return None;
+        }
+
+        attributes.push((attribute, pos..value_end + 1));
+        pos = value_end + 1;
+    }
+}
+
+#[test]
+fn merge_test() {
+    let embedded = br#"<x:xmpmeta><rdf:RDF xmlns:rdf="r"><a/></rdf:RDF></x:xmpmeta>"#;
+    let sidecar = br#"<x:xmpmeta><rdf:RDF xmlns:rdf="r"><b/></rdf:RDF></x:xmpmeta>"#;
+
+    assert_eq!(
+        merge(embedded, sidecar),
+        br#"<x:xmpmeta><rdf:RDF xmlns:rdf="r"><a/><b/></rdf:RDF></x:xmpmeta>"#
+    );
+    assert_eq!(merge(b"", sidecar), sidecar);
+}
+
+#[test]
+fn merge_conflict_test() {
+    let embedded = br#"<rdf:RDF><rdf:Description rdf:about="" xmp:Rating="1" dc:format="a > b"><dc:title><rdf:Alt><rdf:li>Old</rdf:li></rdf:Alt></dc:title><dc:creator/></rdf:Description></rdf:RDF>"#;
+    let sidecar = br#"<rdf:RDF><rdf:Description rdf:about="" xmp:Rating="5"><dc:title><rdf:Alt><rdf:li>New</rdf:li></rdf:Alt></dc:title></rdf:Description></rdf:RDF>"#;
+
+    assert_eq!(
+        String::from_utf8(merge(embedded, sidecar)).unwrap(),
+        r#"<rdf:RDF><rdf:Description rdf:about="" dc:format="a > b"><dc:creator/></rdf:Description><rdf:Description rdf:about="" xmp:Rating="5"><dc:title><rdf:Alt><rdf:li>New</rdf:li></rdf:Alt></dc:title></rdf:Description></rdf:RDF>"#
+    );
+}
@@ -0,0 +1,383 @@
+//! Wrappers for the libheif functions used by the loader
+//!
+//! libheif-rs 0.20 has no API for auxiliary images or image sequences and
+//! doesn't expose its raw handles. Using these wrappers for all images allows
+//! reading the file with a single libheif context.
+
+use glycin_utils::*;
+use libheif_rs::{HeifError, Plane};
+use libheif_sys as lh;
+use std::ffi::CStr;
+use std::ptr;
+
+/// Decoding context that reads from the owned data without copying it
+pub struct Context {
+    inner: *mut lh::heif_context,
+    data: Vec<u8>,
+}
+
+// SAFETY: The context is owned and libheif contexts can be used from any thread
+unsafe impl Send for Context {}
+
+impl Context {
+    pub fn new(data: Vec<u8>) -> Result<Self, DecoderError> {
+        // SAFETY: The data is stored with the context and outlives it
+        unsafe {
+            let inner = lh::heif_context_alloc();
+            if inner.is_null() {
+                return Err(DecoderError::InternalDecoderError);
+            }
+            let context = Self { inner, data };
+
+            let result = lh::heif_context_read_from_memory_without_copy(
+                context.inner,
+                context.data.as_ptr().cast(),
+                context.data.len(),
+                ptr::null(),
+            );
+            HeifError::from_heif_error(result).context_failed()?;
+
+            Ok(context)
+        }
+    }
+
+    pub fn as_ptr(&self) -> *mut lh::heif_context {
+        self.inner
+    }
+
+    /// Frees the context and returns the data it was reading from
+    pub fn into_data(mut self) -> Vec<u8> {
+        std::mem::take(&mut self.data)
+    }
+
+    pub fn primary_image_handle(&self) -> Result<Handle, DecoderError> {
+        let mut handle = ptr::null_mut();
+        // SAFETY: The handle is released on drop
+        let result = unsafe { lh::heif_context_get_primary_image_handle(self.inner, &mut handle) };
+        HeifError::from_heif_error(result).context_failed()?;
+
+        Ok(Handle(handle))
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        // SAFETY: Handles and images keep their own reference to the context data
+        unsafe { lh::heif_context_free(self.inner) }
+    }
+}
+
+/// Encoded image
+pub struct Handle(*mut lh::heif_image_handle);
+
+impl Handle {
+    /// Auxiliary images including alpha and depth images
+    pub fn auxiliary_images(&self) -> Vec<Handle> {
+        // SAFETY: The list has the size reported by libheif
+        unsafe {
+            let n = lh::heif_image_handle_get_number_of_auxiliary_images(self.0, 0);
+            let mut ids = vec![0; usize::try_from(n).unwrap_or_default()];
+            let n = lh::heif_image_handle_get_list_of_auxiliary_image_IDs(
+                self.0,
+                0,
+                ids.as_mut_ptr(),
+                n,
+            );
+            ids.truncate(usize::try_from(n).unwrap_or_default());
+
+            ids.into_iter()
+                .filter_map(|id| {
+                    let mut handle = ptr::null_mut();
+                    let result =
+                        lh::heif_image_handle_get_auxiliary_image_handle(self.0, id, &mut handle);
+                    HeifError::from_heif_error(result).ok()?;
+                    Some(Handle(handle))
+                })
+                .collect()
+        }
+    }
+
+    /// Thumbnail items of the image
+    pub fn thumbnails(&self) -> Vec<Handle> {
+        // SAFETY: The list has the size reported by libheif
+        unsafe {
+            let n = lh::heif_image_handle_get_number_of_thumbnails(self.0);
+            let mut ids = vec![0; usize::try_from(n).unwrap_or_default()];
+            let n = lh::heif_image_handle_get_list_of_thumbnail_IDs(self.0, ids.as_mut_ptr(), n);
+            ids.truncate(usize::try_from(n).unwrap_or_default());
+
+            ids.into_iter()
+                .filter_map(|id| {
+                    let mut handle = ptr::null_mut();
+                    let result = lh::heif_image_handle_get_thumbnail(self.0, id, &mut handle);
+                    HeifError::from_heif_error(result).ok()?;
+                    Some(Handle(handle))
+                })
+                .collect()
+        }
+    }
+
+    /// URN describing the content of auxiliary images
+    pub fn auxiliary_type(&self) -> Option<String> {
+        // SAFETY: The string is copied before it's released
+        unsafe {
+            let mut auxiliary_type = ptr::null();
+            let result = lh::heif_image_handle_get_auxiliary_type(self.0, &mut auxiliary_type);
+            HeifError::from_heif_error(result).ok()?;
+            if auxiliary_type.is_null() {
+                return None;
+            }
+
+            let string = CStr::from_ptr(auxiliary_type).to_string_lossy().to_string();
+            lh::heif_image_handle_release_auxiliary_type(self.0, &mut auxiliary_type);
+
+            Some(string)
+        }
+    }
+
+    /// Size of the image, which depends on whether transformations are applied
+    pub fn size(&self, apply_transformations: bool) -> Result<(u32, u32), DecoderError> {
+        // SAFETY: Only reads properties of a valid handle
+        let (width, height) = unsafe {
+            if apply_transformations {
+                (
+                    lh::heif_image_handle_get_width(self.0),
+                    lh::heif_image_handle_get_height(self.0),
+                )
+            } else {
+                (
+                    lh::heif_image_handle_get_ispe_width(self.0),
+                    lh::heif_image_handle_get_ispe_height(self.0),
+                )
+            }
+        };
+
+        Ok((width.try_u32()?, height.try_u32()?))
+    }
+
+    pub fn luma_bits_per_pixel(&self) -> i32 {
+        // SAFETY: Only reads properties of a valid handle
+        unsafe { lh::heif_image_handle_get_luma_bits_per_pixel(self.0) }
+    }
+
+    pub fn has_alpha_channel(&self) -> bool {
+        // SAFETY: Only reads properties of a valid handle
+        unsafe { lh::heif_image_handle_has_alpha_channel(self.0) != 0 }
+    }
+
+    pub fn is_premultiplied_alpha(&self) -> bool {
+        // SAFETY: Only reads properties of a valid handle
+        unsafe { lh::heif_image_handle_is_premultiplied_alpha(self.0) != 0 }
+    }
+
+    /// First EXIF metadata block without the offset to the TIFF header
+    pub fn exif(&self) -> Option<Vec<u8>> {
+        let id = *self.metadata_ids(b"Exif\0").first()?;
+        let mut exif = self.metadata(id)?;
+
+        if let Some(skip) = exif
+            .get(0..4)
+            .map(|x| u32::from_be_bytes(x.try_into().unwrap()) as usize)
+            .filter(|skip| exif.len() > skip + 4)
+        {
+            exif.drain(0..skip + 4);
+            Some(exif)
+        } else {
+            eprintln!("EXIF data has far too few bytes");
+            None
+        }
+    }
+
+    /// Metadata block with the XMP content type
+    pub fn xmp(&self) -> Option<Vec<u8>> {
+        let id = self.metadata_ids(b"mime\0").into_iter().find(|id| {
+            // SAFETY: The content type is checked for null
+            unsafe {
+                let content_type = lh::heif_image_handle_get_metadata_content_type(self.0, *id);
+                !content_type.is_null()
+                    && CStr::from_ptr(content_type).to_bytes() == b"application/rdf+xml"
+            }
+        })?;
+
+        self.metadata(id)
+    }
+
+    /// Metadata blocks with a null terminated item type
+    fn metadata_ids(&self, item_type: &[u8]) -> Vec<lh::heif_item_id> {
+        let type_filter = item_type.as_ptr().cast();
+
+        // SAFETY: The list has the size reported by libheif
+        unsafe {
+            let n = lh::heif_image_handle_get_number_of_metadata_blocks(self.0, type_filter);
+            let mut ids = vec![0; usize::try_from(n).unwrap_or_default()];
+            let n = lh::heif_image_handle_get_list_of_metadata_block_IDs(
+                self.0,
+                type_filter,
+                ids.as_mut_ptr(),
+                n,
+            );
+            ids.truncate(usize::try_from(n).unwrap_or_default());
+
+            ids
+        }
+    }
+
+    fn metadata(&self, id: lh::heif_item_id) -> Option<Vec<u8>> {
+        // SAFETY: The buffer has the size reported by libheif
+        unsafe {
+            let mut data = vec![0_u8; lh::heif_image_handle_get_metadata_size(self.0, id)];
+            let result = lh::heif_image_handle_get_metadata(self.0, id, data.as_mut_ptr().cast());
+            HeifError::from_heif_error(result).ok()?;
+
+            Some(data)
+        }
+    }
+
+    pub fn decode(
+        &self,
+        colorspace: lh::heif_colorspace,
+        chroma: lh::heif_chroma,
+        apply_transformations: bool,
+    ) -> Result<Image, HeifError> {
+        let mut image = ptr::null_mut();
+        // SAFETY: The image is released on drop
+        let result = unsafe {
+            let options = DecodingOptions::new(apply_transformations);
+            lh::heif_decode_image(self.0, &mut image, colorspace, chroma, options.as_ptr())
+        };
+        HeifError::from_heif_error(result)?;
+
+        Ok(Image(image))
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        // SAFETY: The handle is only released here
+        unsafe { lh::heif_image_handle_release(self.0) }
+    }
+}
+
+/// Decoded image
+pub struct Image(*mut lh::heif_image);
+
+impl Image {
+    /// Takes ownership of an image returned by libheif
+    ///
+    /// # Safety
+    ///
+    /// The pointer must be a valid image that isn't released elsewhere.
+    pub unsafe fn from_raw(image: *mut lh::heif_image) -> Self {
+        Self(image)
+    }
+
+    pub fn is_premultiplied_alpha(&self) -> bool {
+        // SAFETY: Only reads properties of a valid image
+        unsafe { lh::heif_image_is_premultiplied_alpha(self.0) != 0 }
+    }
+
+    pub fn icc_profile(&self) -> Option<Vec<u8>> {
+        // SAFETY: The buffer has the size reported by libheif
+        unsafe {
+            let profile_type = lh::heif_image_get_color_profile_type(self.0);
+            if ![
+                lh::heif_color_profile_type_heif_color_profile_type_rICC,
+                lh::heif_color_profile_type_heif_color_profile_type_prof,
+            ]
+            .contains(&profile_type)
+            {
+                return None;
+            }
+
+            let mut icc_profile = vec![0_u8; lh::heif_image_get_raw_color_profile_size(self.0)];
+            let result =
+                lh::heif_image_get_raw_color_profile(self.0, icc_profile.as_mut_ptr().cast());
+            HeifError::from_heif_error(result).ok()?;
+
+            Some(icc_profile)
+        }
+    }
+
+    pub fn cicp(&self) -> Option<Cicp> {
+        // SAFETY: The profile is freed after reading it
+        unsafe {
+            let mut nclx = ptr::null_mut();
+            let result = lh::heif_image_get_nclx_color_profile(self.0, &mut nclx);
+            if HeifError::from_heif_error(result).is_err() || nclx.is_null() {
+                return None;
+            }
+
+            let cicp = Cicp {
+                color_primaries: crate::nclx_code((*nclx).color_primaries),
+                transfer_characteristics: crate::nclx_code((*nclx).transfer_characteristics),
+                matrix_coefficients: crate::nclx_code((*nclx).matrix_coefficients),
+                video_full_range_flag: (*nclx).full_range_flag != 0,
+            };
+            lh::heif_nclx_color_profile_free(nclx);
+
+            Some(cicp)
+        }
+    }
+
+    pub fn plane(&mut self, channel: lh::heif_channel) -> Result<Plane<&mut [u8]>, DecoderError> {
+        // SAFETY: The plane has `stride * height` bytes and is borrowed from the image
+        unsafe {
+            let mut stride = 0;
+            let data = lh::heif_image_get_plane(self.0, channel, &mut stride);
+            if data.is_null() {
+                return Err(DecoderError::InternalDecoderError);
+            }
+
+            let height = lh::heif_image_get_height(self.0, channel).try_u32()?;
+            let stride = stride.try_usize()?;
+            let size = stride.checked_mul(height.try_usize()?).context_internal()?;
+
+            Ok(Plane {
+                data: std::slice::from_raw_parts_mut(data, size),
+                width: lh::heif_image_get_width(self.0, channel).try_u32()?,
+                height,
+                stride,
+                bits_per_pixel: lh::heif_image_get_bits_per_pixel_range(self.0, channel)
+                    .try_into()
+                    .context_internal()?,
+                storage_bits_per_pixel: lh::heif_image_get_bits_per_pixel(self.0, channel)
+                    .try_into()
+                    .context_internal()?,
+            })
+        }
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        // SAFETY: The image is only released here
+        unsafe { lh::heif_image_release(self.0) }
+    }
+}
+
+/// Options that are freed on drop
+pub struct DecodingOptions(*mut lh::heif_decoding_options);
+
+impl DecodingOptions {
+    pub fn new(apply_transformations: bool) -> Self {
+        // SAFETY: The options are checked for null before they are changed
+        unsafe {
+            let options = lh::heif_decoding_options_alloc();
+            if !options.is_null() && !apply_transformations {
+                (*options).ignore_transformations = 1;
+            }
+            Self(options)
+        }
+    }
+
+    pub fn as_ptr(&self) -> *const lh::heif_decoding_options {
+        self.0
+    }
+}
+
+impl Drop for DecodingOptions {
+    fn drop(&mut self) {
+        // SAFETY: libheif accepts null options
+        unsafe { lh::heif_decoding_options_free(self.0) }
+    }
+}
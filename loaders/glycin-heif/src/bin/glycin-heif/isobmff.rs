@@ -0,0 +1,273 @@
+//! Image sequence tracks in ISOBMFF files as defined in ISO/IEC 14496-12
+//!
+//! Animated AVIF and HEIF files store their frames as samples of a track. The
+//! samples are decoded by libheif, while their timing is read here.
+
+use std::time::Duration;
+
+/// Visual track with the frames of an image sequence
+#[derive(Debug, PartialEq)]
+pub struct SequenceTrack {
+    pub track_id: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Samples use more than 8 bits per channel
+    pub high_bit_depth: bool,
+    /// How often the sequence is played, zero meaning indefinitely
+    pub loop_count: Option<u32>,
+    /// Units per second of the sample durations
+    timescale: u32,
+    /// Number of consecutive samples and their duration from the `stts` box
+    durations: Vec<(u32, u32)>,
+}
+
+impl SequenceTrack {
+    pub fn frame_count(&self) -> u32 {
+        self.durations
+            .iter()
+            .fold(0, |n, (count, _)| n.saturating_add(*count))
+    }
+
+    /// Display duration of the sample with the given index
+    pub fn delay(&self, index: u32) -> Option<Duration> {
+        let mut end = 0_u32;
+        let (_, duration) = self.durations.iter().find(|(count, _)| {
+            end = end.saturating_add(*count);
+            index < end
+        })?;
+
+        if *duration == 0 || self.timescale == 0 {
+            return None;
+        }
+
+        let micros = u64::from(*duration) * 1_000_000 / u64::from(self.timescale);
+        Some(Duration::from_micros(micros))
+    }
+}
+
+/// First track with an image sequence or video
+pub fn sequence_track(data: &[u8]) -> Option<SequenceTrack> {
+    let moov = child(data, b"moov")?;
+
+    boxes(moov)
+        .filter(|(box_type, _)| box_type == b"trak")
+        .find_map(|(_, trak)| track(trak))
+}
+
+fn track(trak: &[u8]) -> Option<SequenceTrack> {
+    let mdia = child(trak, b"mdia")?;
+
+    // Alpha and depth tracks use the `auxv` handler
+    let (_, _, hdlr) = full_box(child(mdia, b"hdlr")?)?;
+    if !matches!(hdlr.get(4..8)?, b"pict" | b"vide") {
+        return None;
+    }
+
+    let (version, _, tkhd) = full_box(child(trak, b"tkhd")?)?;
+    let (track_id, track_duration) = if version == 1 {
+        (
+            u32_at(tkhd, 16)?,
+            Some(u64_at(tkhd, 24)?).filter(|x| *x != u64::MAX),
+        )
+    } else {
+        let duration = u32_at(tkhd, 16)?;
+        (
+            u32_at(tkhd, 8)?,
+            (duration != u32::MAX).then_some(duration.into()),
+        )
+    };
+
+    let (version, _, mdhd) = full_box(child(mdia, b"mdhd")?)?;
+    let timescale = if version == 1 {
+        u32_at(mdhd, 16)?
+    } else {
+        u32_at(mdhd, 8)?
+    };
+
+    let stbl = child(child(mdia, b"minf")?, b"stbl")?;
+
+    let (_, _, stts) = full_box(child(stbl, b"stts")?)?;
+    let n_entries = usize::try_from(u32_at(stts, 0)?).ok()?;
+    let durations = stts
+        .get(4..)?
+        .chunks_exact(8)
+        .take(n_entries)
+        .filter_map(|entry| Some((u32_at(entry, 0)?, u32_at(entry, 4)?)))
+        .collect();
+
+    // Visual sample entry with the codec configuration after 78 bytes
+    let (_, _, stsd) = full_box(child(stbl, b"stsd")?)?;
+    let (codec, sample_entry) = boxes(stsd.get(4..)?).next()?;
+    let width = u16_at(sample_entry, 24)?.into();
+    let height = u16_at(sample_entry, 26)?.into();
+    let high_bit_depth = match &codec {
+        b"av01" => child(sample_entry.get(78..)?, b"av1C")
+            .and_then(|av1c| av1c.get(2))
+            .is_some_and(|x| x & 0x40 != 0),
+        b"hvc1" | b"hev1" => child(sample_entry.get(78..)?, b"hvcC")
+            .and_then(|hvcc| hvcc.get(17))
+            .is_some_and(|x| x & 0x07 != 0),
+        _ => false,
+    };
+
+    let loop_count = child(trak, b"edts")
+        .and_then(|edts| child(edts, b"elst"))
+        .and_then(|elst| loop_count(elst, track_duration));
+
+    Some(SequenceTrack {
+        track_id,
+        width,
+        height,
+        high_bit_depth,
+        loop_count,
+        timescale,
+        durations,
+    })
+}
+
+/// Number of plays given by an edit list that repeats until the track duration is reached
+fn loop_count(elst: &[u8], track_duration: Option<u64>) -> Option<u32> {
+    let (version, flags, elst) = full_box(elst)?;
+
+    if flags & 1 == 0 {
+        return Some(1);
+    }
+
+    // Repeated indefinitely
+    let Some(track_duration) = track_duration else {
+        return Some(0);
+    };
+
+    let segment_duration = if version == 1 {
+        u64_at(elst, 4)?
+    } else {
+        u32_at(elst, 4)?.into()
+    };
+    if segment_duration == 0 {
+        return None;
+    }
+
+    let plays =
+        track_duration / segment_duration + u64::from(track_duration % segment_duration != 0);
+    u32::try_from(plays).ok()
+}
+
+/// Iterates over the types and contents of boxes
+fn boxes(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut pos = 0;
+
+    std::iter::from_fn(move || {
+        let box_type = data.get(pos + 4..pos + 8)?.try_into().ok()?;
+        let (header_size, size) = match u32_at(data, pos)? {
+            // Box extends to the end of the file
+            0 => (8, data.len() - pos),
+            1 => (16, usize::try_from(u64_at(data, pos + 8)?).ok()?),
+            size => (8, usize::try_from(size).ok()?),
+        };
+        let content = data.get(pos + header_size..pos.checked_add(size)?)?;
+        pos += size;
+
+        Some((box_type, content))
+    })
+}
+
+fn child<'a>(data: &'a [u8], box_type: &[u8; 4]) -> Option<&'a [u8]> {
+    boxes(data)
+        .find(|(x, _)| x == box_type)
+        .map(|(_, content)| content)
+}
+
+/// Version, flags, and remaining content of a full box
+fn full_box(data: &[u8]) -> Option<(u8, u32, &[u8])> {
+    let version_flags = u32_at(data, 0)?;
+
+    Some((
+        (version_flags >> 24) as u8,
+        version_flags & 0xFF_FFFF,
+        &data[4..],
+    ))
+}
+
+fn u16_at(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(pos..pos + 2)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+fn u64_at(data: &[u8], pos: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(pos..pos + 8)?.try_into().ok()?))
+}
+
+#[test]
+fn sequence_track_test() {
+    fn new_box(box_type: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        let mut data = u32::try_from(content.len() + 8)
+            .unwrap()
+            .to_be_bytes()
+            .to_vec();
+        data.extend(box_type);
+        data.extend(content);
+        data
+    }
+
+    let mut tkhd = vec![0; 4];
+    tkhd.extend([0; 8]);
+    tkhd.extend(1_u32.to_be_bytes());
+    tkhd.extend([0; 4]);
+    tkhd.extend(300_u32.to_be_bytes());
+
+    // Repeated edit list with a segment duration of 100
+    let mut elst = vec![0, 0, 0, 1];
+    elst.extend(1_u32.to_be_bytes());
+    elst.extend(100_u32.to_be_bytes());
+    elst.extend([0; 8]);
+
+    let mut mdhd = vec![0; 4];
+    mdhd.extend([0; 8]);
+    mdhd.extend(1000_u32.to_be_bytes());
+
+    let mut stts = vec![0; 4];
+    stts.extend(2_u32.to_be_bytes());
+    stts.extend([0, 0, 0, 2, 0, 0, 0, 40]);
+    stts.extend([0, 0, 0, 1, 0, 0, 0, 20]);
+
+    let mut sample_entry = vec![0; 24];
+    sample_entry.extend(64_u16.to_be_bytes());
+    sample_entry.extend(48_u16.to_be_bytes());
+    sample_entry.extend([0; 50]);
+    sample_entry.extend(new_box(b"av1C", &[0x81, 0, 0x40, 0]));
+    let mut stsd = vec![0; 4];
+    stsd.extend(1_u32.to_be_bytes());
+    stsd.extend(new_box(b"av01", &sample_entry));
+
+    let stbl = [new_box(b"stsd", &stsd), new_box(b"stts", &stts)].concat();
+    let mdia = [
+        new_box(b"mdhd", &mdhd),
+        new_box(b"hdlr", &[&[0; 8][..], b"pict"].concat()),
+        new_box(b"minf", &new_box(b"stbl", &stbl)),
+    ]
+    .concat();
+    let trak = [
+        new_box(b"tkhd", &tkhd),
+        new_box(b"edts", &new_box(b"elst", &elst)),
+        new_box(b"mdia", &mdia),
+    ]
+    .concat();
+    let data = [
+        new_box(b"ftyp", b"avis\0\0\0\0avifavis"),
+        new_box(b"moov", &new_box(b"trak", &trak)),
+    ]
+    .concat();
+
+    let track = sequence_track(&data).unwrap();
+    assert_eq!(track.track_id, 1);
+    assert_eq!((track.width, track.height), (64, 48));
+    assert!(track.high_bit_depth);
+    assert_eq!(track.loop_count, Some(3));
+    assert_eq!(track.frame_count(), 3);
+    assert_eq!(track.delay(1), Some(Duration::from_millis(40)));
+    assert_eq!(track.delay(2), Some(Duration::from_millis(20)));
+    assert_eq!(track.delay(3), None);
+}
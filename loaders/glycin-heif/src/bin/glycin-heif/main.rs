@@ -0,0 +1,337 @@
+mod ffi;
+mod isobmff;
+mod sequence;
+
+use glycin_utils::*;
+use libheif_rs::{HeifErrorSubCode, Plane};
+use libheif_sys as lh;
+use std::cell::OnceCell;
+use std::io::Cursor;
+use std::io::Read;
+use std::sync::Mutex;
+
+fn main() {
+    Communication::spawn(ImgDecoder::default());
+}
+
+#[derive(Default)]
+pub struct ImgDecoder {
+    /// Context of still images, sequences own their context instead
+    pub decoder: Mutex<Option<ffi::Context>>,
+    /// Image sequence, which is decoded instead of the still image
+    pub sequence: Mutex<Option<sequence::Sequence>>,
+    pub mime_type: OnceCell<String>,
+    pub apply_transformations: OnceCell<bool>,
+}
+
+impl ImgDecoder {
+    /// Runs `f` with the libheif context, which belongs to the sequence if there is one
+    fn with_context<T>(
+        &self,
+        f: impl FnOnce(&ffi::Context) -> Result<T, DecoderError>,
+    ) -> Result<T, DecoderError> {
+        let sequence = self.sequence.lock().unwrap();
+        let decoder = self.decoder.lock().unwrap();
+        let context = sequence
+            .as_ref()
+            .and_then(sequence::Sequence::context)
+            .or(decoder.as_ref())
+            .context_internal()?;
+        f(context)
+    }
+}
+
+impl Decoder for ImgDecoder {
+    fn init(
+        &self,
+        mut stream: UnixStream,
+        details: DecodingDetails,
+    ) -> Result<ImageInfo, DecoderError> {
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data).context_internal()?;
+
+        let sequence_track = isobmff::sequence_track(&data);
+        let is_sequence = sequence_track.is_some();
+        // Older libheif versions only decode the still image of sequences
+        let sequence_track = sequence_track.filter(|_| sequence::is_supported());
+
+        let context = match ffi::Context::new(data) {
+            Err(_) if is_sequence && sequence_track.is_none() => {
+                return Err(sequence::unsupported())
+            }
+            context => context?,
+        };
+        // Sequences often contain a still image as well, which provides the metadata
+        let handle = match context.primary_image_handle() {
+            Err(_) if sequence_track.is_some() => None,
+            Err(_) if is_sequence => return Err(sequence::unsupported()),
+            handle => Some(handle?),
+        };
+
+        let mut image_info = if let Some(handle) = &handle {
+            let (width, height) = handle.size(details.apply_transformations)?;
+
+            let mut image_info = ImageInfo::new(width, height, "HEIF Container".into());
+            image_info.exif = handle.exif().into();
+            image_info.xmp = handle.xmp().into();
+            image_info.auxiliary_images = Some(auxiliary_images(
+                handle,
+                image_info.exif.as_deref(),
+                details.apply_transformations,
+            )?)
+            .filter(|x| !x.is_empty())
+            .into();
+            image_info.thumbnail_size = largest_thumbnail(handle)
+                .map(|thumbnail| thumbnail.size(details.apply_transformations))
+                .transpose()?
+                .into();
+            image_info
+        } else {
+            let track = sequence_track.as_ref().context_internal()?;
+            ImageInfo::new(track.width, track.height, "HEIF Container".into())
+        };
+        // libheif applies irot, imir, and clap while decoding
+        image_info.transformations_applied = details.apply_transformations;
+
+        if let Some(track) = &sequence_track {
+            image_info.frame_count = Some(track.frame_count()).into();
+            image_info.loop_count = track.loop_count.into();
+        }
+
+        if details.info_only {
+            return Ok(image_info);
+        }
+
+        if let Some(track) = sequence_track {
+            let sequence = sequence::Sequence::new(context, track, details.apply_transformations)?;
+            *self.sequence.lock().unwrap() = Some(sequence);
+        } else {
+            *self.decoder.lock().unwrap() = Some(context);
+        }
+
+        let _ = self.mime_type.set(details.mime_type);
+        let _ = self
+            .apply_transformations
+            .set(details.apply_transformations);
+        Ok(image_info)
+    }
+
+    fn decode_frame(&self, frame_request: FrameRequest) -> Result<Frame, DecoderError> {
+        if frame_request.auxiliary_image.is_none() {
+            if let Some(sequence) = self.sequence.lock().unwrap().as_mut() {
+                return sequence.next_frame();
+            }
+        }
+
+        let apply_transformations = *self.apply_transformations.get().unwrap();
+
+        self.with_context(|context| {
+            let handle = context.primary_image_handle()?;
+
+            if let Some(index) = frame_request.auxiliary_image.as_ref() {
+                let auxiliary_handle = usize::try_from(*index)
+                    .ok()
+                    .and_then(|index| handle.auxiliary_images().into_iter().nth(index))
+                    .ok_or_else(|| {
+                        DecoderError::DecodingError(String::from("Auxiliary image does not exist"))
+                    })?;
+                return decode_gray(&auxiliary_handle, apply_transformations);
+            }
+
+            decode(
+                &handle,
+                self.mime_type.get().unwrap(),
+                apply_transformations,
+            )
+        })
+    }
+
+    fn decode_thumbnail(&self, _frame_request: FrameRequest) -> Result<Frame, DecoderError> {
+        self.with_context(|context| {
+            let handle = context.primary_image_handle()?;
+            let thumbnail = largest_thumbnail(&handle).ok_or_else(|| {
+                DecoderError::DecodingError(String::from("Image has no embedded thumbnail"))
+            })?;
+            decode(
+                &thumbnail,
+                self.mime_type.get().unwrap(),
+                *self.apply_transformations.get().unwrap(),
+            )
+        })
+    }
+}
+
+/// Auxiliary images in the order used for [`FrameRequest::auxiliary_image`]
+fn auxiliary_images(
+    handle: &ffi::Handle,
+    exif: Option<&[u8]>,
+    apply_transformations: bool,
+) -> Result<Vec<AuxiliaryImage>, DecoderError> {
+    handle
+        .auxiliary_images()
+        .iter()
+        .map(|auxiliary_handle| {
+            let auxiliary_type = auxiliary_handle.auxiliary_type().unwrap_or_default();
+            let kind = AuxiliaryImageKind::from_auxiliary_type(&auxiliary_type);
+            let (width, height) = auxiliary_handle.size(apply_transformations)?;
+            let xmp = auxiliary_handle.xmp();
+
+            // ISO 21496-1 gain maps are `tmap` items, which aren't auxiliary images
+            let gain_map = if kind == AuxiliaryImageKind::GainMap {
+                GainMapInfo::from_apple(exif, xmp.as_deref())
+            } else {
+                None
+            };
+
+            Ok(AuxiliaryImage {
+                kind,
+                auxiliary_type,
+                width,
+                height,
+                xmp: xmp.into(),
+                gain_map: gain_map.into(),
+            })
+        })
+        .collect()
+}
+
+/// Thumbnail item with the most pixels
+fn largest_thumbnail(handle: &ffi::Handle) -> Option<ffi::Handle> {
+    handle.thumbnails().into_iter().max_by_key(|thumbnail| {
+        thumbnail
+            .size(true)
+            .map_or(0, |(width, height)| u64::from(width) * u64::from(height))
+    })
+}
+
+fn decode(
+    handle: &ffi::Handle,
+    mime_type: &str,
+    apply_transformations: bool,
+) -> Result<Frame, DecoderError> {
+    let has_alpha = handle.has_alpha_channel();
+    let chroma = match (
+        handle.luma_bits_per_pixel() > 8,
+        has_alpha,
+        cfg!(target_endian = "little"),
+    ) {
+        (false, false, _) => lh::heif_chroma_heif_chroma_interleaved_RGB,
+        (false, true, _) => lh::heif_chroma_heif_chroma_interleaved_RGBA,
+        (true, false, true) => lh::heif_chroma_heif_chroma_interleaved_RRGGBB_LE,
+        (true, false, false) => lh::heif_chroma_heif_chroma_interleaved_RRGGBB_BE,
+        (true, true, true) => lh::heif_chroma_heif_chroma_interleaved_RRGGBBAA_LE,
+        (true, true, false) => lh::heif_chroma_heif_chroma_interleaved_RRGGBBAA_BE,
+    };
+
+    let image_result = handle.decode(
+        lh::heif_colorspace_heif_colorspace_RGB,
+        chroma,
+        apply_transformations,
+    );
+
+    let mut image = match image_result {
+        Err(err) if matches!(err.sub_code, HeifErrorSubCode::UnsupportedCodec) => {
+            return Err(DecoderError::UnsupportedImageFormat(mime_type.to_string()));
+        }
+        image => image.context_failed()?,
+    };
+
+    let icc_profile = image.icc_profile();
+    // Only used if there is no ICC profile
+    let cicp = image.cicp();
+
+    let plane = image.plane(lh::heif_channel_heif_channel_interleaved)?;
+
+    let mut frame = interleaved_frame(plane, has_alpha, handle.is_premultiplied_alpha())?;
+    frame.iccp = icc_profile.into();
+    frame.cicp = cicp.into();
+
+    Ok(frame)
+}
+
+/// Frame from an interleaved RGB or RGBA plane
+fn interleaved_frame(
+    plane: Plane<&mut [u8]>,
+    has_alpha: bool,
+    premultiplied: bool,
+) -> Result<Frame, DecoderError> {
+    let memory_format = if plane.bits_per_pixel > 8 {
+        if let Ok(transmuted) = safe_transmute::transmute_many_pedantic_mut::<u16>(plane.data) {
+            // Scale HDR pixels to 16bit (they are usually 10bit or 12bit)
+            for pixel in transmuted.iter_mut() {
+                *pixel <<= 16 - plane.bits_per_pixel;
+            }
+        } else {
+            eprintln!("Could not transform HDR (16bit) data to u16");
+        }
+
+        if has_alpha {
+            if premultiplied {
+                MemoryFormat::R16g16b16a16Premultiplied
+            } else {
+                MemoryFormat::R16g16b16a16
+            }
+        } else {
+            MemoryFormat::R16g16b16
+        }
+    } else if has_alpha {
+        if premultiplied {
+            MemoryFormat::R8g8b8a8Premultiplied
+        } else {
+            MemoryFormat::R8g8b8a8
+        }
+    } else {
+        MemoryFormat::R8g8b8
+    };
+
+    let mut memory = SharedMemory::new(plane.stride.try_u64()? * u64::from(plane.height));
+    Cursor::new(plane.data).read_exact(&mut memory).unwrap();
+    let texture = memory.into_texture();
+
+    let mut frame = Frame::new(plane.width, plane.height, memory_format, texture);
+    frame.stride = plane.stride.try_u32()?;
+
+    Ok(frame)
+}
+
+/// Decodes an auxiliary image like a depth map as grayscale
+fn decode_gray(handle: &ffi::Handle, apply_transformations: bool) -> Result<Frame, DecoderError> {
+    let mut image = handle
+        .decode(
+            lh::heif_colorspace_heif_colorspace_monochrome,
+            lh::heif_chroma_heif_chroma_monochrome,
+            apply_transformations,
+        )
+        .context_failed()?;
+
+    let is_hdr = handle.luma_bits_per_pixel() > 8;
+    let plane = image.plane(lh::heif_channel_heif_channel_Y)?;
+
+    let memory_format = if is_hdr {
+        if let Ok(transmuted) = safe_transmute::transmute_many_pedantic_mut::<u16>(plane.data) {
+            // Scale to 16bit like for color images
+            for pixel in transmuted.iter_mut() {
+                *pixel <<= 16 - plane.bits_per_pixel;
+            }
+        } else {
+            eprintln!("Could not transform HDR (16bit) data to u16");
+        }
+        MemoryFormat::G16
+    } else {
+        MemoryFormat::G8
+    };
+
+    let mut memory = SharedMemory::new(plane.stride.try_u64()? * u64::from(plane.height));
+    Cursor::new(plane.data).read_exact(&mut memory).unwrap();
+    let texture = memory.into_texture();
+
+    let mut frame = Frame::new(plane.width, plane.height, memory_format, texture);
+    frame.stride = plane.stride.try_u32()?;
+
+    Ok(frame)
+}
+
+/// Codes that don't fit into CICP are reported as unspecified
+fn nclx_code(code: u32) -> u8 {
+    u8::try_from(code).unwrap_or(2)
+}
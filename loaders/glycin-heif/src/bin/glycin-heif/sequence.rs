@@ -0,0 +1,208 @@
+//! Image sequences via the track API of libheif
+//!
+//! libheif decodes sequence tracks since version 1.20, but libheif-rs 0.20 has
+//! no bindings for this API. The functions are therefore looked up at runtime,
+//! which keeps still images working with older versions of libheif. They are
+//! only used if the loaded libheif reports a version with this API.
+
+use glycin_utils::*;
+use libheif_rs::HeifError;
+use libheif_sys as lh;
+use std::ffi::c_void;
+use std::ptr;
+use std::sync::OnceLock;
+
+use crate::ffi;
+use crate::isobmff::SequenceTrack;
+
+/// Opaque `heif_track`
+#[repr(C)]
+struct HeifTrack {
+    _private: [u8; 0],
+}
+
+type GetTrack = unsafe extern "C" fn(*const lh::heif_context, u32) -> *mut HeifTrack;
+type ReleaseTrack = unsafe extern "C" fn(*mut HeifTrack);
+type DecodeNextImage = unsafe extern "C" fn(
+    *mut HeifTrack,
+    *mut *mut lh::heif_image,
+    lh::heif_colorspace,
+    lh::heif_chroma,
+    *const lh::heif_decoding_options,
+) -> lh::heif_error;
+
+/// Functions from `heif_sequences.h`
+struct TrackApi {
+    get_track: GetTrack,
+    release_track: ReleaseTrack,
+    decode_next_image: DecodeNextImage,
+}
+
+fn track_api() -> Option<&'static TrackApi> {
+    static TRACK_API: OnceLock<Option<TrackApi>> = OnceLock::new();
+
+    TRACK_API
+        .get_or_init(|| {
+            // SAFETY: Only returns version numbers
+            let version = unsafe {
+                (
+                    lh::heif_get_version_number_major(),
+                    lh::heif_get_version_number_minor(),
+                )
+            };
+            if version < (1, 20) {
+                return None;
+            }
+
+            let symbol = |name: &[u8]| {
+                // SAFETY: The name is null terminated
+                let symbol = unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr().cast()) };
+                (!symbol.is_null()).then_some(symbol)
+            };
+
+            // SAFETY: The types match the declarations in `heif_sequences.h` of libheif 1.20
+            unsafe {
+                Some(TrackApi {
+                    get_track: std::mem::transmute::<*mut c_void, GetTrack>(symbol(
+                        b"heif_context_get_track\0",
+                    )?),
+                    release_track: std::mem::transmute::<*mut c_void, ReleaseTrack>(symbol(
+                        b"heif_track_release\0",
+                    )?),
+                    decode_next_image: std::mem::transmute::<*mut c_void, DecodeNextImage>(symbol(
+                        b"heif_track_decode_next_image\0",
+                    )?),
+                })
+            }
+        })
+        .as_ref()
+}
+
+/// Whether libheif can decode image sequences
+pub fn is_supported() -> bool {
+    track_api().is_some()
+}
+
+pub fn unsupported() -> DecoderError {
+    DecoderError::UnsupportedImageFormat(String::from(
+        "Image sequences require libheif 1.20 or newer",
+    ))
+}
+
+pub struct Sequence {
+    track: SequenceTrack,
+    apply_transformations: bool,
+    context: Option<ffi::Context>,
+    heif_track: *mut HeifTrack,
+    /// Index of the next sample
+    next_sample: u32,
+}
+
+// SAFETY: The libheif objects are owned by the sequence and not shared
+unsafe impl Send for Sequence {}
+
+impl Sequence {
+    pub fn new(
+        context: ffi::Context,
+        track: SequenceTrack,
+        apply_transformations: bool,
+    ) -> Result<Self, DecoderError> {
+        if !is_supported() {
+            return Err(unsupported());
+        }
+
+        let mut sequence = Self {
+            track,
+            apply_transformations,
+            context: None,
+            heif_track: ptr::null_mut(),
+            next_sample: 0,
+        };
+        sequence.open(context)?;
+
+        Ok(sequence)
+    }
+
+    /// Context of the file, which also provides the still image and its auxiliary images
+    pub fn context(&self) -> Option<&ffi::Context> {
+        self.context.as_ref()
+    }
+
+    /// Decodes the next frame, starting over after the last one
+    pub fn next_frame(&mut self) -> Result<Frame, DecoderError> {
+        if self.next_sample >= self.track.frame_count() {
+            // Tracks can't seek, so the file is read again
+            let data = self.close().context_internal()?;
+            self.open(ffi::Context::new(data)?)?;
+        }
+
+        let api = track_api().context_internal()?;
+
+        let chroma = match (self.track.high_bit_depth, cfg!(target_endian = "little")) {
+            (false, _) => lh::heif_chroma_heif_chroma_interleaved_RGBA,
+            (true, true) => lh::heif_chroma_heif_chroma_interleaved_RRGGBBAA_LE,
+            (true, false) => lh::heif_chroma_heif_chroma_interleaved_RRGGBBAA_BE,
+        };
+
+        let mut image = ptr::null_mut();
+        let options = ffi::DecodingOptions::new(self.apply_transformations);
+        // SAFETY: The track belongs to the open context
+        let result = unsafe {
+            (api.decode_next_image)(
+                self.heif_track,
+                &mut image,
+                lh::heif_colorspace_heif_colorspace_RGB,
+                chroma,
+                options.as_ptr(),
+            )
+        };
+        HeifError::from_heif_error(result).context_failed()?;
+        // SAFETY: The image was returned by libheif
+        let mut image = unsafe { ffi::Image::from_raw(image) };
+
+        let premultiplied = image.is_premultiplied_alpha();
+        let plane = image.plane(lh::heif_channel_heif_channel_interleaved)?;
+        let mut frame = crate::interleaved_frame(plane, true, premultiplied)?;
+        frame.iccp = image.icc_profile().into();
+        frame.cicp = image.cicp().into();
+        frame.delay = self.track.delay(self.next_sample).into();
+        self.next_sample += 1;
+
+        Ok(frame)
+    }
+
+    /// Starts decoding with the first sample of the track
+    fn open(&mut self, context: ffi::Context) -> Result<(), DecoderError> {
+        let api = track_api().context_internal()?;
+
+        // SAFETY: The track is released before the context in `close`
+        self.heif_track = unsafe { (api.get_track)(context.as_ptr(), self.track.track_id) };
+        self.context = Some(context);
+        self.next_sample = 0;
+
+        if self.heif_track.is_null() {
+            return Err(DecoderError::DecodingError(String::from(
+                "Image sequence track not found",
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Releases the track and context and returns the file data
+    fn close(&mut self) -> Option<Vec<u8>> {
+        if let Some(api) = track_api().filter(|_| !self.heif_track.is_null()) {
+            // SAFETY: The track was returned by `get_track` and is only released here
+            unsafe { (api.release_track)(self.heif_track) };
+        }
+        self.heif_track = ptr::null_mut();
+
+        self.context.take().map(ffi::Context::into_data)
+    }
+}
+
+impl Drop for Sequence {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
@@ -0,0 +1,193 @@
+//! CMYK JPEG and TIFF decoding
+//!
+//! image-rs converts CMYK to RGB without color management. These images are
+//! instead decoded to CMYK and converted via their embedded CMYK profile.
+
+use glycin_utils::*;
+
+use std::io::{Cursor, Read, Seek};
+
+/// ICC profile TIFF tag
+const TIFF_TAG_ICC_PROFILE: u16 = 34675;
+
+pub enum CmykDecoder<T> {
+    Jpeg {
+        decoder: jpeg_decoder::Decoder<T>,
+        width: u32,
+        height: u32,
+    },
+    Tiff {
+        reader: T,
+        width: u32,
+        height: u32,
+    },
+}
+
+impl<T: AsRef<[u8]>> CmykDecoder<Cursor<T>> {
+    /// Returns the data unchanged if the JPEG is not CMYK or YCCK encoded
+    pub fn jpeg(data: Cursor<T>) -> Result<Self, Cursor<T>> {
+        let Some(header) =
+            JpegHeader::parse(data.get_ref().as_ref()).filter(|header| header.components == 4)
+        else {
+            return Err(data);
+        };
+
+        let mut decoder = jpeg_decoder::Decoder::new(data);
+        // Without an Adobe marker the data is plain CMYK. The decoder would
+        // otherwise assume inverted CMYK as written by Adobe applications.
+        if !header.adobe {
+            decoder.set_color_transform(jpeg_decoder::ColorTransform::None);
+        }
+
+        Ok(Self::Jpeg {
+            decoder,
+            width: header.width.into(),
+            height: header.height.into(),
+        })
+    }
+
+    /// Returns the data unchanged if the TIFF does not contain CMYK data
+    pub fn tiff(data: Cursor<T>) -> Result<Self, Cursor<T>> {
+        let Ok(mut decoder) = tiff::decoder::Decoder::new(Cursor::new(data.get_ref().as_ref()))
+        else {
+            return Err(data);
+        };
+
+        let is_cmyk = matches!(
+            decoder.colortype(),
+            Ok(tiff::ColorType::CMYK(8) | tiff::ColorType::CMYK(16))
+        );
+
+        match decoder.dimensions() {
+            Ok((width, height)) if is_cmyk => Ok(Self::Tiff {
+                reader: data,
+                width,
+                height,
+            }),
+            _ => Err(data),
+        }
+    }
+}
+
+impl<T: Read + Seek> CmykDecoder<T> {
+    pub fn dimensions(&self) -> (u32, u32) {
+        match self {
+            Self::Jpeg { width, height, .. } | Self::Tiff { width, height, .. } => {
+                (*width, *height)
+            }
+        }
+    }
+
+    pub fn format_name(&self) -> &'static str {
+        match self {
+            Self::Jpeg { .. } => "JPEG",
+            Self::Tiff { .. } => "TIFF",
+        }
+    }
+
+    pub fn frame(self, color_conversion: &ColorConversion) -> Result<Frame, image::ImageError> {
+        self.cmyk_frame(color_conversion).map_err(|err| {
+            image::ImageError::Decoding(image::error::DecodingError::new(
+                image::error::ImageFormatHint::Unknown,
+                err,
+            ))
+        })
+    }
+
+    fn cmyk_frame(self, color_conversion: &ColorConversion) -> Result<Frame, DecoderError> {
+        match self {
+            Self::Jpeg {
+                mut decoder,
+                width,
+                height,
+            } => {
+                let data = decoder.decode().context_failed()?;
+                let iccp = decoder.icc_profile();
+
+                cmyk_frame(
+                    width,
+                    height,
+                    CmykData::U8(data),
+                    iccp.as_deref(),
+                    color_conversion,
+                )
+            }
+            Self::Tiff {
+                reader,
+                width,
+                height,
+            } => {
+                let mut decoder = tiff::decoder::Decoder::new(reader).context_failed()?;
+                let iccp = decoder
+                    .find_tag(tiff::tags::Tag::Unknown(TIFF_TAG_ICC_PROFILE))
+                    .ok()
+                    .flatten()
+                    .and_then(|value| value.into_u8_vec().ok());
+
+                let data = match decoder.read_image().context_failed()? {
+                    tiff::decoder::DecodingResult::U8(data) => CmykData::U8(data),
+                    tiff::decoder::DecodingResult::U16(data) => CmykData::U16(data),
+                    _ => return Err(DecoderError::InternalDecoderError),
+                };
+
+                cmyk_frame(width, height, data, iccp.as_deref(), color_conversion)
+            }
+        }
+    }
+}
+
+/// Parts of the JPEG header relevant for detecting CMYK
+#[derive(Debug, PartialEq, Eq)]
+struct JpegHeader {
+    width: u16,
+    height: u16,
+    components: u8,
+    /// Adobe APP14 marker present
+    adobe: bool,
+}
+
+impl JpegHeader {
+    fn parse(data: &[u8]) -> Option<Self> {
+        let mut adobe = false;
+
+        for (marker, segment) in crate::jpeg::segments(data) {
+            match marker {
+                // APP14
+                0xEE => adobe |= segment.starts_with(b"Adobe"),
+                // SOF markers, excluding DHT, JPG, and DAC
+                0xC0..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                    let [_precision, h1, h0, w1, w0, components] =
+                        segment.get(..6)?.try_into().ok()?;
+                    return Some(Self {
+                        width: u16::from_be_bytes([w1, w0]),
+                        height: u16::from_be_bytes([h1, h0]),
+                        components,
+                        adobe,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+}
+
+#[test]
+fn jpeg_header_test() {
+    let mut data = vec![0xFF, 0xD8];
+    data.extend([0xFF, 0xEE, 0x00, 0x0E]);
+    data.extend(b"Adobe\x00\x64\x00\x00\x00\x00\x02");
+    data.extend([0xFF, 0xC0, 0x00, 0x0E, 8, 0x00, 0x02, 0x00, 0x03, 4]);
+    data.extend([0; 6]);
+
+    assert_eq!(
+        JpegHeader::parse(&data),
+        Some(JpegHeader {
+            width: 3,
+            height: 2,
+            components: 4,
+            adobe: true,
+        })
+    );
+}
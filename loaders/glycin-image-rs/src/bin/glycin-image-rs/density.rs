@@ -0,0 +1,116 @@
+//! Physical resolution stored in the image format
+//!
+//! EXIF resolution is handled by glycin-utils if the format doesn't provide
+//! its own.
+
+use std::io::Cursor;
+use tiff::decoder::ifd::Value;
+use tiff::tags::Tag;
+
+const INCH_PER_METER: f64 = 0.0254;
+const CM_PER_INCH: f64 = 2.54;
+
+/// Horizontal and vertical pixels per inch
+pub fn density(data: &[u8], mime_type: &str) -> Option<(f64, f64)> {
+    match mime_type {
+        "image/bmp" => bmp(data),
+        "image/jpeg" => jpeg(data),
+        "image/png" => png(data),
+        "image/tiff" => tiff(data),
+        _ => None,
+    }
+}
+
+fn bmp(data: &[u8]) -> Option<(f64, f64)> {
+    // Only present in BITMAPINFOHEADER and later versions
+    let header_size = u32::from_le_bytes(data.get(14..18)?.try_into().ok()?);
+    if header_size < 40 {
+        return None;
+    }
+
+    let x = i32::from_le_bytes(data.get(38..42)?.try_into().ok()?);
+    let y = i32::from_le_bytes(data.get(42..46)?.try_into().ok()?);
+
+    Some((f64::from(x) * INCH_PER_METER, f64::from(y) * INCH_PER_METER))
+}
+
+fn jpeg(data: &[u8]) -> Option<(f64, f64)> {
+    let jfif = crate::jpeg::segments(data)
+        // APP0
+        .filter(|(marker, _)| *marker == 0xE0)
+        .find_map(|(_, segment)| segment.strip_prefix(b"JFIF\0"))?;
+
+    let [_major, _minor, units, x0, x1, y0, y1, ..] = *jfif else {
+        return None;
+    };
+    let x = f64::from(u16::from_be_bytes([x0, x1]));
+    let y = f64::from(u16::from_be_bytes([y0, y1]));
+
+    match units {
+        1 => Some((x, y)),
+        2 => Some((x * CM_PER_INCH, y * CM_PER_INCH)),
+        // Only specifies the aspect ratio
+        _ => None,
+    }
+}
+
+fn png(data: &[u8]) -> Option<(f64, f64)> {
+    let phys = crate::png::chunks(data)
+        .take_while(|(chunk_type, _)| chunk_type != b"IDAT")
+        .find(|(chunk_type, _)| chunk_type == b"pHYs")
+        .map(|(_, chunk_data)| chunk_data)?;
+
+    let [x0, x1, x2, x3, y0, y1, y2, y3, unit] = *phys else {
+        return None;
+    };
+
+    // Unit 0 only specifies the aspect ratio
+    if unit != 1 {
+        return None;
+    }
+
+    let x = f64::from(u32::from_be_bytes([x0, x1, x2, x3]));
+    let y = f64::from(u32::from_be_bytes([y0, y1, y2, y3]));
+
+    Some((x * INCH_PER_METER, y * INCH_PER_METER))
+}
+
+fn tiff(data: &[u8]) -> Option<(f64, f64)> {
+    let mut decoder = tiff::decoder::Decoder::new(Cursor::new(data)).ok()?;
+
+    let mut rational = |tag| match decoder.find_tag(tag).ok()?? {
+        Value::Rational(n, d) if d != 0 => Some(f64::from(n) / f64::from(d)),
+        _ => None,
+    };
+    let x = rational(Tag::XResolution)?;
+    let y = rational(Tag::YResolution)?;
+
+    // Defaults to inch
+    let unit = decoder
+        .find_tag(Tag::ResolutionUnit)
+        .ok()
+        .flatten()
+        .and_then(|x| x.into_u16().ok())
+        .unwrap_or(2);
+
+    match unit {
+        2 => Some((x, y)),
+        3 => Some((x * CM_PER_INCH, y * CM_PER_INCH)),
+        _ => None,
+    }
+}
+
+#[test]
+fn png_density_test() {
+    let mut data = crate::png::SIGNATURE.to_vec();
+    data.extend(9_u32.to_be_bytes());
+    data.extend(b"pHYs");
+    // 11811 pixels per meter are 300 DPI
+    data.extend(11811_u32.to_be_bytes());
+    data.extend(11811_u32.to_be_bytes());
+    data.push(1);
+    data.extend([0; 4]);
+
+    let (x, y) = png(&data).unwrap();
+    assert_eq!((x.round(), y.round()), (300., 300.));
+}
@@ -0,0 +1,105 @@
+//! Minimal parsing of TIFF structures, like in EXIF data
+
+pub struct Tiff<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+pub struct Entry {
+    pub tag: u16,
+    pub field_type: u16,
+    pub count: u32,
+    /// Position of the value or of the offset to the values
+    pos: usize,
+}
+
+impl<'a> Tiff<'a> {
+    pub fn new(data: &'a [u8]) -> Option<Self> {
+        let big_endian = match data.get(..4)? {
+            b"MM\0*" => true,
+            b"II*\0" => false,
+            _ => return None,
+        };
+
+        Some(Self { data, big_endian })
+    }
+
+    pub fn u16(&self, pos: usize) -> Option<u16> {
+        let bytes = self.data.get(pos..pos + 2)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    pub fn u32(&self, pos: usize) -> Option<u32> {
+        let bytes = self.data.get(pos..pos + 4)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    pub fn bytes(&self, offset: u32, len: u32) -> Option<&'a [u8]> {
+        let offset = usize::try_from(offset).ok()?;
+        let len = usize::try_from(len).ok()?;
+        self.data.get(offset..offset.checked_add(len)?)
+    }
+
+    /// Entries and offset of the next IFD
+    pub fn ifd(&self, offset: u32) -> Option<(Vec<Entry>, u32)> {
+        let pos = usize::try_from(offset).ok()?;
+        let n_entries = usize::from(self.u16(pos)?);
+
+        let entries = (0..n_entries)
+            .map(|i| {
+                let pos = pos + 2 + i * 12;
+                Some(Entry {
+                    tag: self.u16(pos)?,
+                    field_type: self.u16(pos + 2)?,
+                    count: self.u32(pos + 4)?,
+                    pos: pos + 8,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        let next_ifd = self.u32(pos + 2 + n_entries * 12).unwrap_or(0);
+
+        Some((entries, next_ifd))
+    }
+
+    /// Values of SHORT, LONG, and IFD entries
+    pub fn values(&self, entry: &Entry) -> Vec<u32> {
+        let size = match entry.field_type {
+            // SHORT
+            3 => 2,
+            // LONG or IFD
+            4 | 13 => 4,
+            _ => return Vec::new(),
+        };
+        // Only small lists are expected for the relevant tags
+        let count = entry.count.min(64) as usize;
+
+        let Some(pos) = self.value_pos(entry, size) else {
+            return Vec::new();
+        };
+
+        (0..count)
+            .map_while(|i| match size {
+                2 => self.u16(pos + i * 2).map(u32::from),
+                _ => self.u32(pos + i * 4),
+            })
+            .collect()
+    }
+
+    /// Position of the values, which are stored in the entry if they fit
+    pub fn value_pos(&self, entry: &Entry, size: usize) -> Option<usize> {
+        if size.saturating_mul(usize::try_from(entry.count).ok()?) <= 4 {
+            Some(entry.pos)
+        } else {
+            self.u32(entry.pos).and_then(|x| usize::try_from(x).ok())
+        }
+    }
+}
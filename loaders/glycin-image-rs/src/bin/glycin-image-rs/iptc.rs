@@ -0,0 +1,92 @@
+//! IPTC-IIM extraction
+
+use std::io::Cursor;
+
+use tiff::decoder::ifd::Value;
+
+/// Signature of Photoshop image resource blocks in JPEG APP13 segments
+const PHOTOSHOP_SIGNATURE: &[u8] = b"Photoshop 3.0\0";
+
+/// Image resource ID of IPTC-IIM data
+const IRB_ID_IPTC: u16 = 0x0404;
+
+/// IPTC-NAA TIFF tag
+const TIFF_TAG_IPTC: u16 = 33723;
+
+pub fn iptc(data: &[u8], mime_type: &str) -> Option<Vec<u8>> {
+    match mime_type {
+        "image/jpeg" => jpeg(data),
+        "image/tiff" => tiff(data),
+        _ => None,
+    }
+    .filter(|iptc| !iptc.is_empty())
+}
+
+fn jpeg(data: &[u8]) -> Option<Vec<u8>> {
+    crate::jpeg::segments(data)
+        // APP13
+        .filter(|(marker, _)| *marker == 0xED)
+        .find_map(|(_, segment)| segment.strip_prefix(PHOTOSHOP_SIGNATURE))
+        .and_then(image_resource_iptc)
+}
+
+/// Finds the IPTC data in Photoshop image resource blocks
+fn image_resource_iptc(mut blocks: &[u8]) -> Option<Vec<u8>> {
+    while let Some(block) = blocks.strip_prefix(b"8BIM") {
+        let id = u16::from_be_bytes(block.get(..2)?.try_into().ok()?);
+        // Pascal string, padded to an even size
+        let name_len = usize::from(*block.get(2)?);
+        let pos = 2 + (name_len + 2) / 2 * 2;
+        let size = u32::from_be_bytes(block.get(pos..pos + 4)?.try_into().ok()?);
+        let size = usize::try_from(size).ok()?;
+        let resource = block.get(pos + 4..(pos + 4).checked_add(size)?)?;
+
+        if id == IRB_ID_IPTC {
+            return Some(resource.to_vec());
+        }
+
+        // Resource data is padded to an even size as well
+        blocks = block.get(pos + 4 + size + size % 2..)?;
+    }
+
+    None
+}
+
+fn tiff(data: &[u8]) -> Option<Vec<u8>> {
+    let value = tiff::decoder::Decoder::new(Cursor::new(data))
+        .ok()?
+        .find_tag(tiff::tags::Tag::Unknown(TIFF_TAG_IPTC))
+        .ok()??;
+
+    let values = match value {
+        Value::List(values) => values,
+        value => vec![value],
+    };
+
+    // The tag is often stored as LONG instead of UNDEFINED
+    let big_endian = data.starts_with(b"MM");
+    let mut iptc = Vec::new();
+    for value in values {
+        match value {
+            Value::Byte(byte) => iptc.push(byte),
+            Value::Unsigned(long) if big_endian => iptc.extend(long.to_be_bytes()),
+            Value::Unsigned(long) => iptc.extend(long.to_le_bytes()),
+            _ => return None,
+        }
+    }
+
+    Some(iptc)
+}
+
+#[test]
+fn image_resource_test() {
+    let mut blocks = Vec::new();
+    // Unrelated resource with name
+    blocks.extend(b"8BIM\x03\xED\x03abc\0\0\0\x01x\0");
+    blocks.extend(b"8BIM\x04\x04\0\0\0\0\0\x03\x1C\x02\x00\0");
+
+    assert_eq!(
+        image_resource_iptc(&blocks).as_deref(),
+        Some(&b"\x1C\x02\x00"[..])
+    );
+}
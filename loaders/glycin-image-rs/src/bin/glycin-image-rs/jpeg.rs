@@ -0,0 +1,47 @@
+//! JPEG segment parsing
+
+/// Start of scan, after which the entropy coded data follows
+const SOS: u8 = 0xDA;
+
+/// Iterates over the markers and data of all segments before the image data
+pub fn segments(data: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    // Start after the SOI marker
+    let mut pos = if data.starts_with(&[0xFF, 0xD8]) {
+        2
+    } else {
+        data.len()
+    };
+
+    std::iter::from_fn(move || {
+        let [0xFF, marker] = *data.get(pos..pos + 2)? else {
+            return None;
+        };
+
+        if marker == SOS {
+            return None;
+        }
+
+        let len = usize::from(u16::from_be_bytes(
+            data.get(pos + 2..pos + 4)?.try_into().ok()?,
+        ));
+        let segment = data.get(pos + 4..pos + 2 + len.max(2))?;
+        pos += 2 + len.max(2);
+
+        Some((marker, segment))
+    })
+}
+
+/// Dimensions from the start of frame segment
+pub fn dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    segments(data)
+        .find(|(marker, _)| matches!(marker, 0xC0..=0xCF) && ![0xC4, 0xC8, 0xCC].contains(marker))
+        .and_then(|(_, segment)| {
+            let height = u16::from_be_bytes(segment.get(1..3)?.try_into().ok()?);
+            let width = u16::from_be_bytes(segment.get(3..5)?.try_into().ok()?);
+            Some((u32::from(width), u32::from(height))).filter(|(w, h)| *w > 0 && *h > 0)
+        })
+}
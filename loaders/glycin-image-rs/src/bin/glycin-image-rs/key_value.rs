@@ -0,0 +1,146 @@
+//! Generic key/value metadata from PNG text chunks and GIF comments
+
+use std::collections::BTreeMap;
+
+/// Maximum number of entries
+const MAX_ENTRIES: usize = 128;
+
+/// Maximum combined size of all keys and values in bytes
+const MAX_TOTAL_SIZE: usize = 1024 * 1024;
+
+/// Key used for GIF comment extensions
+const GIF_COMMENT_KEY: &str = "Comment";
+
+pub fn key_value(data: &[u8], mime_type: &str) -> Option<BTreeMap<String, String>> {
+    let entries: Vec<(String, String)> = match mime_type {
+        "image/png" => crate::png::text_chunks(data, |keyword| {
+            // Exposed separately as XMP or EXIF
+            keyword != "XML:com.adobe.xmp" && !keyword.starts_with("Raw profile type")
+        })
+        .map(|x| (x.keyword, x.text))
+        .collect(),
+        "image/gif" => {
+            let comments = gif_comments(data);
+            if comments.is_empty() {
+                Vec::new()
+            } else {
+                vec![(GIF_COMMENT_KEY.to_string(), comments.join("\n"))]
+            }
+        }
+        _ => Vec::new(),
+    };
+
+    let mut key_value = BTreeMap::new();
+    let mut total_size = 0;
+
+    for (key, value) in entries {
+        if key_value.len() >= MAX_ENTRIES {
+            break;
+        }
+
+        total_size += key.len() + value.len();
+        if total_size > MAX_TOTAL_SIZE {
+            break;
+        }
+
+        // Keep the first entry for repeated keys
+        key_value.entry(key).or_insert(value);
+    }
+
+    Some(key_value).filter(|x| !x.is_empty())
+}
+
+/// Text of all comment extensions
+fn gif_comments(data: &[u8]) -> Vec<String> {
+    let mut comments = Vec::new();
+
+    if !data.starts_with(b"GIF87a") && !data.starts_with(b"GIF89a") {
+        return comments;
+    }
+
+    // Header and logical screen descriptor
+    let mut pos = 13;
+    let Some(flags) = data.get(10) else {
+        return comments;
+    };
+    pos += color_table_size(*flags);
+
+    while let Some(block) = data.get(pos) {
+        match block {
+            // Extension
+            0x21 => {
+                let Some(label) = data.get(pos + 1) else {
+                    break;
+                };
+                let Some((sub_blocks, end)) = sub_blocks(data, pos + 2) else {
+                    break;
+                };
+                if *label == 0xFE {
+                    let comment = sub_blocks.iter().map(|x| char::from(*x)).collect();
+                    comments.push(comment);
+                }
+                pos = end;
+            }
+            // Image descriptor
+            0x2C => {
+                let Some(flags) = data.get(pos + 9) else {
+                    break;
+                };
+                // Descriptor, color table, and LZW minimum code size
+                let image_data = pos + 10 + color_table_size(*flags) + 1;
+                let Some((_, end)) = sub_blocks(data, image_data) else {
+                    break;
+                };
+                pos = end;
+            }
+            // Trailer or invalid data
+            _ => break,
+        }
+    }
+
+    comments
+}
+
+/// Size of the color table following a descriptor with the given flags
+fn color_table_size(flags: u8) -> usize {
+    if flags & 0x80 == 0 {
+        0
+    } else {
+        3 * (1 << ((flags & 0x07) + 1))
+    }
+}
+
+/// Concatenated data of sub-blocks starting at `pos` and the position after them
+fn sub_blocks(data: &[u8], mut pos: usize) -> Option<(Vec<u8>, usize)> {
+    let mut content = Vec::new();
+
+    loop {
+        let len = usize::from(*data.get(pos)?);
+        pos += 1;
+        if len == 0 {
+            return Some((content, pos));
+        }
+
+        if content.len() < MAX_TOTAL_SIZE {
+            content.extend_from_slice(data.get(pos..pos + len)?);
+        }
+        pos += len;
+    }
+}
+
+#[test]
+fn gif_comments_test() {
+    let mut data = b"GIF89a".to_vec();
+    // Logical screen descriptor with a global color table of two entries
+    data.extend([1, 0, 1, 0, 0x80, 0, 0]);
+    data.extend([0; 6]);
+    // Comment extension
+    data.extend(b"\x21\xFE\x05Hello\x00");
+    // Image descriptor without local color table
+    data.extend([0x2C, 0, 0, 0, 0, 1, 0, 1, 0, 0]);
+    data.extend([2, 2, 0x4C, 0x01, 0]);
+    data.extend(b"\x21\xFE\x03abc\x00");
+    data.push(0x3B);
+
+    assert_eq!(gif_comments(&data), ["Hello", "abc"]);
+}
@@ -0,0 +1,471 @@
+#![allow(clippy::large_enum_variant)]
+
+mod cmyk;
+mod density;
+mod ifd;
+mod iptc;
+mod jpeg;
+mod key_value;
+mod mpf;
+mod openexr;
+mod pfm;
+mod png;
+mod thumbnail;
+mod xmp;
+
+use glycin_utils::*;
+use image::codecs;
+use image::AnimationDecoder;
+
+use std::io::Cursor;
+use std::io::Read;
+use std::ops::Range;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+
+fn main() {
+    Communication::spawn(ImgDecoder::default());
+}
+
+type Reader = Cursor<Vec<u8>>;
+
+#[derive(Default)]
+pub struct ImgDecoder {
+    pub decoder: Mutex<Option<ImageRsDecoder<Reader>>>,
+    pub thread: Mutex<Option<(std::thread::JoinHandle<()>, Receiver<Frame>)>>,
+    /// Image data for formats that support requests for specific frames
+    pub data: Mutex<Option<Reader>>,
+    pub png_color_chunks: Mutex<Option<png::ColorChunks>>,
+    /// JPEG data of the embedded thumbnail
+    pub thumbnail: Mutex<Option<Vec<u8>>>,
+    /// Position of the auxiliary images' JPEG data in [`Self::data`]
+    pub auxiliary_images: Mutex<Vec<Range<usize>>>,
+}
+
+fn worker(decoder: ImageRsDecoder<Reader>, data: Reader, mime_type: String, send: Sender<Frame>) {
+    let mut decoder = Some(decoder);
+
+    std::thread::park();
+
+    let mut nth_frame = 1;
+
+    loop {
+        if decoder.is_none() {
+            decoder = ImageRsDecoder::new(data.clone(), &mime_type).ok();
+        }
+
+        // Use transparent background instead of suggested background color
+        if let Some(ImageRsDecoder::WebP(webp)) = &mut decoder {
+            let _result = webp.set_background_color(image::Rgba::from([0, 0, 0, 0]));
+        }
+
+        let frames = std::mem::take(&mut decoder).unwrap().into_frames().unwrap();
+
+        for frame in frames {
+            match frame {
+                Err(err) => {
+                    eprintln!("Skipping frame: {err}");
+                }
+                Ok(frame) => {
+                    nth_frame += 1;
+
+                    let (delay_num, delay_den) = frame.delay().numer_denom_ms();
+
+                    let delay = if delay_num == 0 || delay_den == 0 {
+                        None
+                    } else {
+                        let micros = f64::round(delay_num as f64 * 1000. / delay_den as f64) as u64;
+                        Some(std::time::Duration::from_micros(micros))
+                    };
+
+                    let buffer = frame.into_buffer();
+
+                    let memory_format = MemoryFormat::R8g8b8a8;
+                    let width = buffer.width();
+                    let height = buffer.height();
+
+                    let mut memory = SharedMemory::new(
+                        u64::from(width) * u64::from(height) * memory_format.n_bytes().u64(),
+                    );
+                    Cursor::new(buffer.into_raw())
+                        .read_exact(&mut memory)
+                        .unwrap();
+                    let texture = memory.into_texture();
+
+                    let mut out_frame = Frame::new(width, height, memory_format, texture);
+                    out_frame.delay = delay.into();
+
+                    send.send(out_frame).unwrap();
+
+                    // If not really an animation no need to keep the thread around
+                    if delay.is_none() {
+                        return;
+                    }
+                }
+            }
+
+            std::thread::park();
+        }
+
+        if nth_frame == 1 {
+            panic!("No frames found");
+        }
+    }
+}
+
+impl Decoder for ImgDecoder {
+    fn init(
+        &self,
+        mut stream: UnixStream,
+        details: DecodingDetails,
+    ) -> Result<ImageInfo, DecoderError> {
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).context_internal()?;
+        let data = Cursor::new(buf);
+
+        let mut decoder = ImageRsDecoder::new(data.clone(), &details.mime_type)?;
+        let mut image_info = decoder.info();
+
+        let exif = exif::Reader::new().read_from_container(&mut data.clone());
+        image_info.exif = exif.ok().map(|x| x.buf().to_vec()).into();
+        image_info.xmp = xmp::xmp(data.get_ref(), &details.mime_type).into();
+        image_info.iptc = iptc::iptc(data.get_ref(), &details.mime_type).into();
+        image_info.key_value = key_value::key_value(data.get_ref(), &details.mime_type).into();
+
+        let thumbnail = image_info
+            .exif
+            .as_ref()
+            .and_then(|exif| thumbnail::thumbnail(exif, image_info.width, image_info.height));
+        if let Some(thumbnail) = &thumbnail {
+            image_info.thumbnail_size = Some((thumbnail.width, thumbnail.height)).into();
+        }
+
+        if let Some((x_ppi, y_ppi)) = density::density(data.get_ref(), &details.mime_type) {
+            image_info.set_resolution(x_ppi, y_ppi);
+        }
+
+        if matches!(decoder, ImageRsDecoder::Png(_)) {
+            let text_chunks: Vec<_> = png::text_chunks(data.get_ref(), |keyword| {
+                matches!(keyword, "Title" | "Description")
+            })
+            .collect();
+            image_info.title =
+                png::localized_text(&text_chunks, "Title", &details.languages).into();
+            image_info.description =
+                png::localized_text(&text_chunks, "Description", &details.languages).into();
+        }
+
+        if matches!(decoder, ImageRsDecoder::OpenExr(_)) {
+            image_info.layers = Some(openexr::layers(data.clone())?).into();
+        }
+
+        let (auxiliary_images, auxiliary_ranges): (Vec<_>, Vec<_>) =
+            if details.mime_type == "image/jpeg" {
+                mpf::auxiliary_images(data.get_ref()).into_iter().unzip()
+            } else {
+                Default::default()
+            };
+        if !auxiliary_images.is_empty() {
+            image_info.auxiliary_images = Some(auxiliary_images).into();
+        }
+
+        if details.info_only {
+            return Ok(image_info);
+        }
+
+        *self.thumbnail.lock().unwrap() = thumbnail.map(|thumbnail| thumbnail.data);
+
+        if matches!(decoder, ImageRsDecoder::Png(_)) {
+            *self.png_color_chunks.lock().unwrap() = Some(png::ColorChunks::parse(data.get_ref()));
+        }
+
+        // Layers and auxiliary images are decoded from the file data
+        let keep_data =
+            matches!(decoder, ImageRsDecoder::OpenExr(_)) || !auxiliary_ranges.is_empty();
+        *self.auxiliary_images.lock().unwrap() = auxiliary_ranges;
+
+        if decoder.is_animated() {
+            let (send, recv) = channel();
+            let thead = std::thread::spawn(move || worker(decoder, data, details.mime_type, send));
+            *self.thread.lock().unwrap() = Some((thead, recv));
+        } else {
+            *self.decoder.lock().unwrap() = Some(decoder);
+            if keep_data {
+                *self.data.lock().unwrap() = Some(data);
+            }
+        }
+
+        Ok(image_info)
+    }
+
+    fn decode_frame(&self, frame_request: FrameRequest) -> Result<Frame, DecoderError> {
+        if let Some((part, name)) = frame_request.layer.as_ref() {
+            let data = self.data.lock().unwrap().clone().ok_or_else(|| {
+                DecoderError::DecodingError(String::from("Format does not support layers"))
+            })?;
+            return openexr::layer_frame(data, *part, name);
+        }
+
+        if let Some(index) = frame_request.auxiliary_image.as_ref() {
+            let range = usize::try_from(*index)
+                .ok()
+                .and_then(|index| self.auxiliary_images.lock().unwrap().get(index).cloned())
+                .ok_or_else(|| {
+                    DecoderError::DecodingError(String::from("Auxiliary image does not exist"))
+                })?;
+            let data = self
+                .data
+                .lock()
+                .unwrap()
+                .as_ref()
+                .and_then(|data| data.get_ref().get(range))
+                .map(<[u8]>::to_vec)
+                .context_internal()?;
+            return ImageRsDecoder::new(Cursor::new(data), "image/jpeg")?
+                .frame(&frame_request.color_conversion)
+                .context_failed()
+                .map_err(Into::into);
+        }
+
+        let mut frame = if let Some(decoder) = std::mem::take(&mut *self.decoder.lock().unwrap()) {
+            decoder
+                .frame(&frame_request.color_conversion)
+                .context_failed()?
+        } else if let Some((ref thread, ref recv)) = *self.thread.lock().unwrap() {
+            thread.thread().unpark();
+            recv.recv().unwrap()
+        } else {
+            return Err(DecoderError::InternalDecoderError);
+        };
+
+        if let Some(color_chunks) = self.png_color_chunks.lock().unwrap().as_ref() {
+            if frame.iccp.is_none() {
+                let gray = frame.memory_format.n_channels() <= 2;
+                frame.iccp = color_chunks.icc_profile(gray).into();
+            }
+            frame.rendering_intent = color_chunks.rendering_intent().into();
+        }
+
+        Ok(frame)
+    }
+
+    fn decode_thumbnail(&self, frame_request: FrameRequest) -> Result<Frame, DecoderError> {
+        let data = self.thumbnail.lock().unwrap().clone().ok_or_else(|| {
+            DecoderError::DecodingError(String::from("Image has no embedded thumbnail"))
+        })?;
+
+        ImageRsDecoder::new(Cursor::new(data), "image/jpeg")?
+            .frame(&frame_request.color_conversion)
+            .context_failed()
+            .map_err(Into::into)
+    }
+}
+
+pub enum ImageRsDecoder<T: std::io::Read + std::io::Seek> {
+    Bmp(codecs::bmp::BmpDecoder<T>),
+    Cmyk(cmyk::CmykDecoder<T>),
+    Dds(codecs::dds::DdsDecoder<T>),
+    Farbfeld(codecs::farbfeld::FarbfeldDecoder<T>),
+    Gif(codecs::gif::GifDecoder<T>),
+    Hdr(codecs::hdr::HdrDecoder<T>),
+    Ico(codecs::ico::IcoDecoder<T>),
+    Jpeg(codecs::jpeg::JpegDecoder<T>),
+    OpenExr(codecs::openexr::OpenExrDecoder<T>),
+    Png(codecs::png::PngDecoder<T>),
+    Pfm(pfm::PfmDecoder<T>),
+    Pnm(codecs::pnm::PnmDecoder<T>),
+    Qoi(codecs::qoi::QoiDecoder<T>),
+    Tga(codecs::tga::TgaDecoder<T>),
+    Tiff(codecs::tiff::TiffDecoder<T>),
+    WebP(codecs::webp::WebPDecoder<T>),
+}
+
+impl ImageRsDecoder<Reader> {
+    fn new(data: Reader, mime_type: &str) -> Result<Self, DecoderError> {
+        Ok(match mime_type {
+            "image/bmp" => Self::Bmp(codecs::bmp::BmpDecoder::new(data).context_failed()?),
+            "image/x-dds" => Self::Dds(codecs::dds::DdsDecoder::new(data).context_failed()?),
+            "image/x-ff" => {
+                Self::Farbfeld(codecs::farbfeld::FarbfeldDecoder::new(data).context_failed()?)
+            }
+            "image/gif" => Self::Gif(codecs::gif::GifDecoder::new(data).context_failed()?),
+            "image/vnd.radiance" => {
+                let data_len = data.get_ref().len();
+                let decoder = codecs::hdr::HdrDecoder::new(data).context_failed()?;
+                let metadata = decoder.metadata();
+                check_hdr_size(metadata.width, metadata.height, data_len)?;
+                Self::Hdr(decoder)
+            }
+            "image/vnd.microsoft.icon" => {
+                Self::Ico(codecs::ico::IcoDecoder::new(data).context_failed()?)
+            }
+            "image/jpeg" => match cmyk::CmykDecoder::jpeg(data) {
+                Ok(decoder) => Self::Cmyk(decoder),
+                Err(data) => Self::Jpeg(codecs::jpeg::JpegDecoder::new(data).context_failed()?),
+            },
+            "image/x-exr" => {
+                Self::OpenExr(codecs::openexr::OpenExrDecoder::new(data).context_failed()?)
+            }
+            "image/png" => Self::Png(codecs::png::PngDecoder::new(data).context_failed()?),
+            "image/x-portable-floatmap" => Self::Pfm(pfm::PfmDecoder::new(data).context_failed()?),
+            "image/x-portable-bitmap"
+            | "image/x-portable-graymap"
+            | "image/x-portable-pixmap"
+            | "image/x-portable-anymap" => {
+                Self::Pnm(codecs::pnm::PnmDecoder::new(data).context_failed()?)
+            }
+            "image/x-qoi" => Self::Qoi(codecs::qoi::QoiDecoder::new(data).context_failed()?),
+            "image/x-targa" | "image/x-tga" => {
+                Self::Tga(codecs::tga::TgaDecoder::new(data).context_failed()?)
+            }
+            "image/tiff" => match cmyk::CmykDecoder::tiff(data) {
+                Ok(decoder) => Self::Cmyk(decoder),
+                Err(data) => Self::Tiff(codecs::tiff::TiffDecoder::new(data).context_failed()?),
+            },
+            "image/webp" => Self::WebP(codecs::webp::WebPDecoder::new(data).context_failed()?),
+
+            mime_type => return Err(DecoderError::UnsupportedImageFormat(mime_type.to_string())),
+        })
+    }
+}
+
+impl<'a, T: std::io::BufRead + std::io::Seek + 'a> ImageRsDecoder<T> {
+    fn info(&mut self) -> ImageInfo {
+        match self {
+            Self::Bmp(d) => ImageInfo::from_decoder(d, "BMP"),
+            Self::Cmyk(d) => {
+                let (width, height) = d.dimensions();
+                ImageInfo::new(width, height, d.format_name().into())
+            }
+            Self::Dds(d) => ImageInfo::from_decoder(d, "DDS"),
+            Self::Farbfeld(d) => ImageInfo::from_decoder(d, "Farbfeld"),
+            Self::Gif(d) => ImageInfo::from_decoder(d, "GIF"),
+            Self::Hdr(d) => {
+                let metadata = d.metadata();
+                ImageInfo::new(metadata.width, metadata.height, "Radiance HDR".into())
+            }
+            Self::Ico(d) => ImageInfo::from_decoder(d, "ICO"),
+            Self::Jpeg(d) => ImageInfo::from_decoder(d, "JPEG"),
+            Self::OpenExr(d) => ImageInfo::from_decoder(d, "OpenEXR"),
+            Self::Pfm(d) => {
+                let (width, height) = d.dimensions();
+                ImageInfo::new(width, height, "PFM".into())
+            }
+            Self::Png(d) => ImageInfo::from_decoder(d, "PNG"),
+            Self::Pnm(d) => ImageInfo::from_decoder(d, "PNM"),
+            Self::Qoi(d) => ImageInfo::from_decoder(d, "QOI"),
+            Self::Tga(d) => ImageInfo::from_decoder(d, "TGA"),
+            Self::Tiff(d) => ImageInfo::from_decoder(d, "TIFF"),
+            Self::WebP(d) => ImageInfo::from_decoder(d, "WebP"),
+        }
+    }
+
+    /// CMYK data is directly converted into the target of the color conversion
+    fn frame(self, color_conversion: &ColorConversion) -> Result<Frame, image::ImageError> {
+        let is_linear = matches!(self, Self::Hdr(_) | Self::OpenExr(_) | Self::Pfm(_));
+
+        let mut frame = match self {
+            Self::Bmp(d) => Frame::from_decoder(d),
+            Self::Cmyk(d) => d.frame(color_conversion),
+            Self::Dds(d) => Frame::from_decoder(d),
+            Self::Farbfeld(d) => Frame::from_decoder(d),
+            Self::Gif(d) => Frame::from_decoder(d),
+            Self::Hdr(d) => hdr_frame(d),
+            Self::Ico(d) => Frame::from_decoder(d),
+            Self::Jpeg(d) => Frame::from_decoder(d),
+            Self::OpenExr(d) => Frame::from_decoder(d),
+            Self::Pfm(d) => d.frame(),
+            Self::Png(d) => Frame::from_decoder(d),
+            Self::Pnm(d) => Frame::from_decoder(d),
+            Self::Qoi(d) => Frame::from_decoder(d),
+            Self::Tga(d) => Frame::from_decoder(d),
+            Self::Tiff(d) => Frame::from_decoder(d),
+            Self::WebP(d) => Frame::from_decoder(d),
+        }?;
+
+        if is_linear {
+            frame.cicp = Some(Cicp::LINEAR_SRGB).into();
+        }
+
+        Ok(frame)
+    }
+
+    fn into_frames(self) -> Option<image::Frames<'a>> {
+        match self {
+            Self::Png(d) => Some(d.apng().into_frames()),
+            Self::Gif(d) => Some(d.into_frames()),
+            Self::WebP(d) => Some(d.into_frames()),
+            _ => None,
+        }
+    }
+
+    fn is_animated(&self) -> bool {
+        match self {
+            Self::Gif(_) => true,
+            Self::Png(d) => d.is_apng(),
+            Self::WebP(d) => d.has_animation(),
+            _ => false,
+        }
+    }
+}
+
+fn hdr_frame<T: std::io::BufRead>(
+    decoder: codecs::hdr::HdrDecoder<T>,
+) -> Result<Frame, image::ImageError> {
+    let memory_format = MemoryFormat::R32g32b32Float;
+    let metadata = decoder.metadata();
+    let (width, height) = (metadata.width, metadata.height);
+
+    let memory_size = u64::from(width)
+        .checked_mul(u64::from(height))
+        .and_then(|x| x.checked_mul(memory_format.n_bytes().u64()))
+        .ok_or_else(|| {
+            image::ImageError::Limits(image::error::LimitError::from_kind(
+                image::error::LimitErrorKind::DimensionError,
+            ))
+        })?;
+
+    let pixels = decoder.read_image_hdr()?;
+
+    let mut memory = SharedMemory::new(memory_size);
+    for (pixel, out_pixel) in pixels
+        .iter()
+        .zip(memory.chunks_exact_mut(memory_format.n_bytes().usize()))
+    {
+        for (value, out_value) in pixel.0.iter().zip(out_pixel.chunks_exact_mut(4)) {
+            out_value.copy_from_slice(&value.to_ne_bytes());
+        }
+    }
+    let texture = memory.into_texture();
+
+    Ok(Frame::new(width, height, memory_format, texture))
+}
+
+/// Rejects dimensions the data can't hold, before image-rs allocates the image
+///
+/// Run-length encoded scanlines need at least one run per 127 values of each
+/// channel. Other widths only allow the old encoding, which isn't accounted
+/// for, so these images have to be uncompressed.
+fn check_hdr_size(width: u32, height: u32, data_len: usize) -> Result<(), DecoderError> {
+    let min_scanline_len = if (8..=0x7FFF).contains(&width) {
+        4 + 4 * 2 * ((u64::from(width) + 126) / 127)
+    } else {
+        u64::from(width) * 4
+    };
+
+    let min_len = min_scanline_len.checked_mul(u64::from(height));
+    if min_len.is_some_and(|len| len <= data_len.try_u64().unwrap_or(u64::MAX)) {
+        Ok(())
+    } else {
+        Err(DecoderError::DecodingError(format!(
+            "Radiance HDR data too short for dimensions: {width}x{height}"
+        )))
+    }
+}
+
+#[test]
+fn hdr_size_test() {
+    assert!(check_hdr_size(1000, 1000, 1_000_000).is_ok());
+    assert!(check_hdr_size(30_000, 30_000, 1_000_000).is_err());
+    assert!(check_hdr_size(u32::MAX, u32::MAX, usize::MAX).is_err());
+}
@@ -0,0 +1,161 @@
+//! Multi-Picture Format (MPF) as defined in CIPA DC-007
+//!
+//! The MP index in an APP2 segment of the first image lists further JPEG
+//! images that are appended to the file.
+
+use glycin_utils::{AuxiliaryImage, AuxiliaryImageKind, GainMapInfo};
+use std::ops::Range;
+
+use crate::ifd::Tiff;
+
+/// Signature of MPF data in JPEG APP2 segments
+const SIGNATURE: &[u8] = b"MPF\0";
+
+/// Signature of ISO 21496-1 gain map metadata in JPEG APP2 segments
+const ISO_21496_SIGNATURE: &[u8] = b"urn:iso:std:iso:ts:21496:-1\0";
+
+const TAG_MP_ENTRY: u16 = 0xB002;
+
+/// Image type codes
+const TYPE_LARGE_THUMBNAIL_VGA: u32 = 0x01_0001;
+const TYPE_LARGE_THUMBNAIL_FULL_HD: u32 = 0x01_0002;
+const TYPE_PANORAMA: u32 = 0x02_0001;
+const TYPE_DISPARITY: u32 = 0x02_0002;
+const TYPE_MULTI_ANGLE: u32 = 0x02_0003;
+
+/// Size of an MP entry in bytes
+const MP_ENTRY_SIZE: usize = 16;
+
+/// Limit for images listed in malformed files
+const MAX_IMAGES: usize = 64;
+
+pub struct MpfImage<'a> {
+    /// Image type code from the MP entry
+    pub type_code: u32,
+    /// JPEG data
+    pub data: &'a [u8],
+    /// Position of the JPEG data in the file
+    pub range: Range<usize>,
+}
+
+/// Images following the primary image
+pub fn secondary_images(data: &[u8]) -> Vec<MpfImage<'_>> {
+    crate::jpeg::segments(data)
+        // APP2
+        .filter(|(marker, _)| *marker == 0xE2)
+        .find_map(|(_, segment)| segment.strip_prefix(SIGNATURE))
+        .and_then(|mpf| images(data, mpf))
+        .unwrap_or_default()
+}
+
+fn images<'a>(data: &'a [u8], mpf: &[u8]) -> Option<Vec<MpfImage<'a>>> {
+    // Image offsets are relative to the MPF header within the file
+    let header_pos = (mpf.as_ptr() as usize).checked_sub(data.as_ptr() as usize)?;
+
+    let tiff = Tiff::new(mpf)?;
+    let (entries, _) = tiff.ifd(tiff.u32(4)?)?;
+    let entry = entries
+        .iter()
+        // UNDEFINED
+        .find(|entry| entry.tag == TAG_MP_ENTRY && entry.field_type == 7)?;
+    let pos = tiff.value_pos(entry, 1)?;
+    let n_images = usize::try_from(entry.count).ok()? / MP_ENTRY_SIZE;
+
+    // The first entry is the primary image
+    let images = (1..n_images.min(MAX_IMAGES))
+        .filter_map(|i| {
+            let pos = pos + i * MP_ENTRY_SIZE;
+            let attribute = tiff.u32(pos)?;
+            let size = usize::try_from(tiff.u32(pos + 4)?).ok()?;
+            let offset = usize::try_from(tiff.u32(pos + 8)?).ok()?;
+
+            let start = header_pos.checked_add(offset)?;
+            let range = start..start.checked_add(size)?;
+            let image = data.get(range.clone())?;
+
+            image.starts_with(&[0xFF, 0xD8]).then_some(MpfImage {
+                type_code: attribute & 0xFF_FFFF,
+                data: image,
+                range,
+            })
+        })
+        .collect();
+
+    Some(images)
+}
+
+/// Gain map parameters from the ISO 21496-1 or Ultra HDR metadata of an image
+pub fn gain_map_info(data: &[u8]) -> Option<GainMapInfo> {
+    crate::jpeg::segments(data)
+        // APP2
+        .filter(|(marker, _)| *marker == 0xE2)
+        .find_map(|(_, segment)| segment.strip_prefix(ISO_21496_SIGNATURE))
+        .and_then(GainMapInfo::from_iso_21496)
+        .or_else(|| GainMapInfo::from_xmp(&crate::xmp::xmp(data, "image/jpeg")?))
+}
+
+/// Secondary images listed as auxiliary images, with the position of their JPEG data
+pub fn auxiliary_images(data: &[u8]) -> Vec<(AuxiliaryImage, Range<usize>)> {
+    secondary_images(data)
+        .into_iter()
+        .filter_map(|image| {
+            let (width, height) = crate::jpeg::dimensions(image.data)?;
+            let gain_map = gain_map_info(image.data);
+
+            let auxiliary_image = AuxiliaryImage {
+                kind: kind(image.type_code, gain_map.is_some()),
+                auxiliary_type: format!("mpf:{:06x}", image.type_code),
+                width,
+                height,
+                xmp: crate::xmp::xmp(image.data, "image/jpeg").into(),
+                gain_map: gain_map.into(),
+            };
+
+            Some((auxiliary_image, image.range))
+        })
+        .collect()
+}
+
+/// Gain maps use the type code for undefined images, all other images are JPEGs with colors
+fn kind(type_code: u32, is_gain_map: bool) -> AuxiliaryImageKind {
+    match type_code {
+        _ if is_gain_map => AuxiliaryImageKind::GainMap,
+        TYPE_LARGE_THUMBNAIL_VGA | TYPE_LARGE_THUMBNAIL_FULL_HD => AuxiliaryImageKind::Preview,
+        // Disparity images are the further views of stereo images
+        TYPE_PANORAMA | TYPE_DISPARITY | TYPE_MULTI_ANGLE => AuxiliaryImageKind::View,
+        _ => AuxiliaryImageKind::Image,
+    }
+}
+
+#[test]
+fn secondary_images_test() {
+    let image = [0xFF, 0xD8, 0xFF, 0xD9];
+
+    let mut mpf = b"MPF\0II*\0".to_vec();
+    mpf.extend(8_u32.to_le_bytes());
+    mpf.extend(1_u16.to_le_bytes());
+    mpf.extend(TAG_MP_ENTRY.to_le_bytes());
+    mpf.extend(7_u16.to_le_bytes());
+    mpf.extend(32_u32.to_le_bytes());
+    mpf.extend(26_u32.to_le_bytes());
+    mpf.extend(0_u32.to_le_bytes());
+    // Primary image, followed by a disparity image
+    mpf.extend([0, 0, 3, 0]);
+    mpf.extend([0; 12]);
+    mpf.extend([2, 0, 2, 0]);
+    mpf.extend(4_u32.to_le_bytes());
+    mpf.extend(62_u32.to_le_bytes());
+    mpf.extend([0; 4]);
+
+    let mut data = vec![0xFF, 0xD8, 0xFF, 0xE2];
+    data.extend(u16::try_from(mpf.len() + 2).unwrap().to_be_bytes());
+    data.extend(mpf);
+    data.extend([0xFF, 0xDA, 0, 2]);
+    data.extend(image);
+
+    let images = secondary_images(&data);
+    assert_eq!(images.len(), 1);
+    assert_eq!(kind(images[0].type_code, false), AuxiliaryImageKind::View);
+    assert_eq!(images[0].data, image);
+    assert_eq!(images[0].range, 72..76);
+}
@@ -0,0 +1,354 @@
+//! Access to all layers and parts of OpenEXR files
+//!
+//! image-rs only decodes the first RGBA layer. This module uses the exr crate
+//! directly to list and decode the other layers.
+
+use glycin_utils::*;
+
+use exr::block::chunk::TileCoordinates;
+use exr::block::reader::ChunksReader;
+use exr::block::BlockIndex;
+use exr::compression::Compression;
+use exr::meta::attribute::{ChannelDescription, SampleType};
+use exr::meta::header::Header;
+use exr::meta::MetaData;
+
+use std::io::{Read, Seek, SeekFrom};
+
+/// Lists the layers of all parts of the image
+///
+/// Channels named like `diffuse.R` are grouped into the layer `diffuse`.
+/// Channels without a prefix are grouped into a layer with an empty name.
+pub fn layers(data: impl Read + Seek) -> Result<Vec<ImageLayer>, DecoderError> {
+    let meta = MetaData::read_from_buffered(data, false).context_failed()?;
+    let mut layers = Vec::new();
+
+    for (part, header) in meta.headers.iter().enumerate() {
+        let part_name = header
+            .own_attributes
+            .layer_name
+            .as_ref()
+            .map(|x| x.to_string())
+            .unwrap_or_default();
+
+        let mut part_layers: Vec<ImageLayer> = Vec::new();
+        for channel in &header.channels.list {
+            let (name, channel_name) = split_channel_name(&channel.name.to_string());
+
+            if let Some(layer) = part_layers.iter_mut().find(|x| x.name == name) {
+                layer.channels.push(channel_name);
+            } else {
+                part_layers.push(ImageLayer {
+                    part: part.try_u32()?,
+                    part_name: part_name.clone(),
+                    name,
+                    channels: vec![channel_name],
+                    width: header.layer_size.width().try_u32()?,
+                    height: header.layer_size.height().try_u32()?,
+                });
+            }
+        }
+
+        layers.append(&mut part_layers);
+    }
+
+    Ok(layers)
+}
+
+/// Decodes the given layer of the given part
+///
+/// If `name` is the full name of a single channel, only that channel is
+/// decoded and returned as grayscale.
+pub fn layer_frame(
+    mut data: impl Read + Seek,
+    part: u32,
+    name: &str,
+) -> Result<Frame, DecoderError> {
+    let data_len = data.seek(SeekFrom::End(0)).context_failed()?;
+    data.rewind().context_failed()?;
+
+    let reader = exr::block::read(data, false).context_failed()?;
+    let part = part.try_usize()?;
+
+    let header = reader
+        .headers()
+        .get(part)
+        .ok_or_else(|| DecoderError::DecodingError(format!("No part with index {part}")))?
+        .clone();
+
+    let selection = ChannelSelection::new(&header, name)
+        .ok_or_else(|| DecoderError::DecodingError(format!("No layer or channel {name:?}")))?;
+
+    let width = header.layer_size.width();
+    let height = header.layer_size.height();
+    let n_pixels = width.checked_mul(height).context_failed()?;
+
+    // Reject dimensions that can't be backed by the data before allocating
+    let max_len = data_len.saturating_mul(max_compression_ratio(header.compression));
+    if !n_pixels
+        .checked_mul(header.channels.bytes_per_pixel)
+        .is_some_and(|len| len.try_u64().is_ok_and(|len| len <= max_len))
+    {
+        return Err(DecoderError::DecodingError(format!(
+            "EXR data too short for dimensions: {width}x{height}"
+        )));
+    }
+
+    let mut channels = vec![vec![0_f32; n_pixels]; selection.channels.len()];
+
+    reader
+        .filter_chunks(
+            false,
+            |_meta: &MetaData, _tile: TileCoordinates, block: BlockIndex| {
+                block.layer == part && block.level == exr::math::Vec2(0, 0)
+            },
+        )
+        .context_failed()?
+        .decompress_sequential(false, |_meta, block| {
+            for line in block.lines(&header.channels) {
+                let Some(target) = selection
+                    .channels
+                    .iter()
+                    .position(|x| *x == line.location.channel)
+                else {
+                    continue;
+                };
+
+                let position = line.location.position;
+                let start = position.y() * width + position.x();
+                let Some(out) = channels[target].get_mut(start..start + line.location.sample_count)
+                else {
+                    continue;
+                };
+
+                match header.channels.list[line.location.channel].sample_type {
+                    SampleType::F16 => {
+                        for (out, value) in
+                            out.iter_mut().zip(line.read_samples::<exr::prelude::f16>())
+                        {
+                            *out = value?.to_f32();
+                        }
+                    }
+                    SampleType::F32 => line.read_samples_into_slice(out)?,
+                    SampleType::U32 => {
+                        for (out, value) in out.iter_mut().zip(line.read_samples::<u32>()) {
+                            *out = value? as f32;
+                        }
+                    }
+                }
+            }
+
+            Ok(())
+        })
+        .context_failed()?;
+
+    if selection.normalize {
+        normalize(&mut channels[0]);
+    }
+
+    let memory_format = if selection.channels.len() == 4 {
+        MemoryFormat::R32g32b32a32Float
+    } else {
+        MemoryFormat::R32g32b32Float
+    };
+    let n_channels = usize::from(memory_format.n_channels());
+
+    let mut memory = SharedMemory::new(n_pixels.try_u64()? * memory_format.n_bytes().u64());
+    for (i, out_pixel) in memory
+        .chunks_exact_mut(memory_format.n_bytes().usize())
+        .enumerate()
+    {
+        for (c, out_value) in out_pixel.chunks_exact_mut(4).enumerate().take(n_channels) {
+            let value = if channels.len() == 1 {
+                // Grayscale values are repeated for all three channels
+                channels[0][i]
+            } else {
+                channels.get(c).map_or(0., |x| x[i])
+            };
+            out_value.copy_from_slice(&value.to_ne_bytes());
+        }
+    }
+    let texture = memory.into_texture();
+
+    let mut frame = Frame::new(width.try_u32()?, height.try_u32()?, memory_format, texture);
+    if !selection.normalize {
+        frame.cicp = Some(Cicp::LINEAR_SRGB).into();
+    }
+
+    Ok(frame)
+}
+
+/// Channels of a part that are combined into a frame
+struct ChannelSelection {
+    /// Indices of the channels within the part, up to four channels
+    channels: Vec<usize>,
+    /// Single data channel, like depth, that has to be scaled to be visible
+    normalize: bool,
+}
+
+impl ChannelSelection {
+    fn new(header: &Header, name: &str) -> Option<Self> {
+        let list = &header.channels.list;
+
+        // Full channel name selects a single channel
+        if let Some(index) = list
+            .iter()
+            .position(|x| x.name.to_string() == name && is_supported(x))
+        {
+            let (_, channel_name) = split_channel_name(name);
+            return Some(Self {
+                channels: vec![index],
+                normalize: !is_color_channel(&channel_name),
+            });
+        }
+
+        let layer_channels: Vec<(usize, &ChannelDescription)> = list
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| split_channel_name(&x.name.to_string()).0 == name && is_supported(x))
+            .collect();
+
+        let find = |channel_name: &str| {
+            layer_channels
+                .iter()
+                .find(|(_, x)| split_channel_name(&x.name.to_string()).1 == channel_name)
+                .map(|(i, _)| *i)
+        };
+
+        let alpha = find("A");
+
+        let mut channels: Vec<usize> = match (find("R"), find("G"), find("B"), find("Y")) {
+            (Some(r), Some(g), Some(b), _) => vec![r, g, b],
+            (_, _, _, Some(y)) => vec![y],
+            _ => layer_channels
+                .iter()
+                .map(|(i, _)| *i)
+                .filter(|i| Some(*i) != alpha)
+                .take(3)
+                .collect(),
+        };
+
+        // Two data channels, like motion vectors, end up in red and green
+        let normalize = match channels.as_slice() {
+            [] => return None,
+            [single] => !is_color_channel(&split_channel_name(&list[*single].name.to_string()).1),
+            _ => false,
+        };
+
+        if channels.len() == 3 {
+            if let Some(alpha) = alpha {
+                channels.push(alpha);
+            }
+        }
+
+        Some(Self {
+            channels,
+            normalize,
+        })
+    }
+}
+
+fn split_channel_name(name: &str) -> (String, String) {
+    match name.rsplit_once('.') {
+        Some((layer, channel)) => (layer.to_string(), channel.to_string()),
+        None => (String::new(), name.to_string()),
+    }
+}
+
+/// Subsampled channels are not supported
+fn is_supported(channel: &ChannelDescription) -> bool {
+    channel.sampling == exr::math::Vec2(1, 1)
+}
+
+fn is_color_channel(channel_name: &str) -> bool {
+    ["R", "G", "B", "Y", "A"].contains(&channel_name)
+}
+
+/// Upper bound for the ratio of uncompressed to compressed data
+fn max_compression_ratio(compression: Compression) -> u64 {
+    match compression {
+        Compression::Uncompressed => 1,
+        // Runs of up to 128 bytes are stored in two bytes
+        Compression::RLE => 64,
+        // 32 bytes of a flat block are stored in three bytes
+        Compression::B44 | Compression::B44A => 16,
+        // zlib can't exceed about 1032:1, PIZ stores runs in Huffman codes
+        _ => 2048,
+    }
+}
+
+/// Scales values to the range from 0 to 1
+fn normalize(values: &mut [f32]) {
+    let (min, max) = values
+        .iter()
+        .filter(|x| x.is_finite())
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), x| {
+            (min.min(*x), max.max(*x))
+        });
+
+    let range = max - min;
+    if range.is_finite() && range > 0. {
+        for value in values {
+            *value = ((*value - min) / range).clamp(0., 1.);
+        }
+    }
+}
+
+#[test]
+fn layers_test() {
+    use exr::prelude::*;
+
+    let channels = |names: &[&str]| {
+        AnyChannels::sort(
+            names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| AnyChannel::new(*name, FlatSamples::F32(vec![i as f32 / 4.; 2])))
+                .collect(),
+        )
+    };
+
+    let layer = |name, channel_names| {
+        Layer::new(
+            (2, 1),
+            LayerAttributes::named(name),
+            Encoding::UNCOMPRESSED,
+            channels(channel_names),
+        )
+    };
+
+    let image = Image::from_layers(
+        ImageAttributes::new(IntegerBounds::from_dimensions((2, 1))),
+        vec![
+            layer("first", &["diffuse.R", "diffuse.G", "diffuse.B", "depth.Z"]),
+            layer("second", &["Y"]),
+        ],
+    );
+
+    let mut data = std::io::Cursor::new(Vec::new());
+    image.write().to_buffered(&mut data).unwrap();
+    data.set_position(0);
+
+    let layers = layers(data.clone()).unwrap();
+    let names: Vec<_> = layers
+        .iter()
+        .map(|x| (x.part, x.part_name.as_str(), x.name.as_str()))
+        .collect();
+    assert_eq!(
+        names,
+        [
+            (0, "first", "depth"),
+            (0, "first", "diffuse"),
+            (1, "second", "")
+        ]
+    );
+    assert_eq!(layers[1].channels, ["B", "G", "R"]);
+
+    let frame = layer_frame(data.clone(), 0, "diffuse").unwrap();
+    assert_eq!((frame.width, frame.height), (2, 1));
+    assert!(matches!(frame.memory_format, MemoryFormat::R32g32b32Float));
+
+    assert!(layer_frame(data.clone(), 1, "").is_ok());
+    assert!(layer_frame(data.clone(), 2, "").is_err());
+    assert!(layer_frame(data, 0, "specular").is_err());
+}
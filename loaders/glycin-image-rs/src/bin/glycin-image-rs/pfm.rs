@@ -0,0 +1,180 @@
+//! Portable Float Map (PFM) decoder
+//!
+//! The format is not supported by image-rs. A PFM file consists of a short
+//! text header followed by uncompressed 32-bit floats, stored bottom-to-top.
+
+use glycin_utils::*;
+
+use std::io::{BufRead, Read, Seek, SeekFrom};
+
+pub struct PfmDecoder<T> {
+    reader: T,
+    width: u32,
+    height: u32,
+    channels: u8,
+    little_endian: bool,
+}
+
+impl<T: BufRead + Seek> PfmDecoder<T> {
+    pub fn new(mut reader: T) -> Result<Self, image::ImageError> {
+        let channels = match read_token(&mut reader)?.as_str() {
+            "PF" => 3,
+            "Pf" => 1,
+            magic => return Err(invalid_data(format!("Unknown PFM magic: {magic:?}"))),
+        };
+
+        let width = parse_token::<u32>(&mut reader)?;
+        let height = parse_token::<u32>(&mut reader)?;
+        // The sign of the scale signals the byte order, its magnitude is not used in practice
+        let scale = parse_token::<f32>(&mut reader)?;
+
+        if width == 0 || height == 0 {
+            return Err(invalid_data(format!(
+                "Invalid PFM dimensions: {width}x{height}"
+            )));
+        }
+
+        // Reject dimensions before anything is allocated for them
+        let data_len = u64::from(width)
+            .checked_mul(u64::from(height))
+            .and_then(|x| x.checked_mul(u64::from(channels) * 4));
+        if !data_len.is_some_and(|len| len <= remaining_len(&mut reader).unwrap_or(0)) {
+            return Err(invalid_data(format!(
+                "PFM data too short for dimensions: {width}x{height}"
+            )));
+        }
+
+        Ok(Self {
+            reader,
+            width,
+            height,
+            channels,
+            little_endian: scale.is_sign_negative(),
+        })
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn frame(mut self) -> Result<Frame, image::ImageError> {
+        let memory_format = MemoryFormat::R32g32b32Float;
+        let n_bytes = memory_format.n_bytes().usize();
+
+        let width = self.width.try_usize().map_err(conversion_error)?;
+        let height = self.height.try_usize().map_err(conversion_error)?;
+        let row_len = width
+            .checked_mul(self.channels.into())
+            .and_then(|x| x.checked_mul(4))
+            .ok_or_else(|| conversion_error(ConversionTooLargerError))?;
+        let out_stride = width
+            .checked_mul(n_bytes)
+            .ok_or_else(|| conversion_error(ConversionTooLargerError))?;
+
+        let memory_size = out_stride
+            .checked_mul(height)
+            .ok_or_else(|| conversion_error(ConversionTooLargerError))?;
+        let mut memory = SharedMemory::new(memory_size.try_u64().map_err(conversion_error)?);
+
+        let mut row = vec![0; row_len];
+        // Rows are stored from bottom to top
+        for y in (0..height).rev() {
+            self.reader.read_exact(&mut row)?;
+
+            let out_row = &mut memory[y * out_stride..(y + 1) * out_stride];
+            for (x, out_pixel) in out_row.chunks_exact_mut(n_bytes).enumerate() {
+                for (c, out_value) in out_pixel.chunks_exact_mut(4).enumerate() {
+                    // Grayscale values are repeated for all three channels
+                    let c = if self.channels == 1 { 0 } else { c };
+                    let i = (x * usize::from(self.channels) + c) * 4;
+                    let bytes: [u8; 4] = row[i..i + 4].try_into().unwrap();
+
+                    let value = if self.little_endian {
+                        f32::from_le_bytes(bytes)
+                    } else {
+                        f32::from_be_bytes(bytes)
+                    };
+
+                    out_value.copy_from_slice(&value.to_ne_bytes());
+                }
+            }
+        }
+
+        let texture = memory.into_texture();
+
+        Ok(Frame::new(self.width, self.height, memory_format, texture))
+    }
+}
+
+/// Number of bytes after the current position
+fn remaining_len(reader: &mut impl Seek) -> std::io::Result<u64> {
+    let pos = reader.stream_position()?;
+    let end = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(pos))?;
+
+    Ok(end.saturating_sub(pos))
+}
+
+/// Reads a whitespace separated header token and the single whitespace following it
+fn read_token(reader: &mut impl BufRead) -> Result<String, image::ImageError> {
+    let mut token = Vec::new();
+
+    for byte in reader.bytes() {
+        let byte = byte?;
+        if byte.is_ascii_whitespace() {
+            if token.is_empty() {
+                continue;
+            } else {
+                break;
+            }
+        }
+
+        token.push(byte);
+
+        if token.len() > 32 {
+            return Err(invalid_data("PFM header token too long".into()));
+        }
+    }
+
+    String::from_utf8(token).map_err(|err| invalid_data(err.to_string()))
+}
+
+fn parse_token<N: std::str::FromStr>(reader: &mut impl BufRead) -> Result<N, image::ImageError> {
+    let token = read_token(reader)?;
+    token
+        .parse()
+        .map_err(|_| invalid_data(format!("Invalid PFM header value: {token:?}")))
+}
+
+fn invalid_data(msg: String) -> image::ImageError {
+    image::ImageError::IoError(std::io::Error::new(std::io::ErrorKind::InvalidData, msg))
+}
+
+fn conversion_error(err: ConversionTooLargerError) -> image::ImageError {
+    image::ImageError::IoError(std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+}
+
+#[test]
+fn header_test() {
+    let mut data = b"Pf\n2 1\n-1.0\n".to_vec();
+    data.extend(0.5_f32.to_le_bytes());
+    data.extend(2.0_f32.to_le_bytes());
+
+    let decoder = PfmDecoder::new(std::io::Cursor::new(data)).unwrap();
+    assert_eq!(decoder.dimensions(), (2, 1));
+    assert_eq!(decoder.channels, 1);
+    assert!(decoder.little_endian);
+    assert!(decoder.frame().is_ok());
+}
+
+#[test]
+fn truncated_test() {
+    let mut data = b"PF
+65535 65535
+-1.0
+"
+    .to_vec();
+    data.extend([0; 12]);
+
+    assert!(PfmDecoder::new(std::io::Cursor::new(data)).is_err());
+}
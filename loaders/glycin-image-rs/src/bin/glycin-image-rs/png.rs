@@ -0,0 +1,304 @@
+//! PNG chunk parsing
+//!
+//! PNGs without an `iCCP` chunk can describe their colors via the legacy
+//! `gAMA` and `cHRM` chunks. image-rs ignores these chunks, so an equivalent
+//! ICC profile is created for them.
+
+use glycin_utils::*;
+
+use std::io::Read;
+
+pub const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+/// Decompressed text chunks are truncated to this size
+pub const MAX_TEXT_SIZE: u64 = 64 * 1024;
+
+/// Maximum number of text chunks that are read
+const MAX_TEXT_CHUNKS: usize = 128;
+
+/// Maximum combined size of all keywords and texts in bytes
+const MAX_TOTAL_TEXT_SIZE: u64 = 1024 * 1024;
+
+/// The common gamma of 1/2.2, which is used as an approximation of sRGB
+const GAMMA_SRGB: u32 = 45455;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ColorChunks {
+    /// Encoding gamma times 100000
+    gamma: Option<u32>,
+    /// Red, green, blue, and white point
+    chromaticities: Option<[(f64, f64); 4]>,
+    srgb: bool,
+    /// Rendering intent from the `sRGB` chunk
+    rendering_intent: Option<RenderingIntent>,
+    iccp: bool,
+}
+
+impl ColorChunks {
+    /// Reads the color chunks, which have to be placed before the image data
+    pub fn parse(data: &[u8]) -> Self {
+        let mut color_chunks = Self::default();
+
+        for (chunk_type, chunk_data) in chunks(data) {
+            match &chunk_type {
+                b"IDAT" => break,
+                b"sRGB" => {
+                    color_chunks.srgb = true;
+                    color_chunks.rendering_intent = match chunk_data {
+                        [0] => Some(RenderingIntent::Perceptual),
+                        [1] => Some(RenderingIntent::RelativeColorimetric),
+                        [2] => Some(RenderingIntent::Saturation),
+                        [3] => Some(RenderingIntent::AbsoluteColorimetric),
+                        _ => None,
+                    };
+                }
+                b"iCCP" => color_chunks.iccp = true,
+                b"gAMA" => {
+                    color_chunks.gamma = chunk_data
+                        .try_into()
+                        .ok()
+                        .map(u32::from_be_bytes)
+                        .filter(|gamma| *gamma > 0);
+                }
+                b"cHRM" if chunk_data.len() == 32 => {
+                    let value = |i: usize| {
+                        let bytes = chunk_data[i * 4..i * 4 + 4].try_into().unwrap();
+                        f64::from(u32::from_be_bytes(bytes)) / 100_000.
+                    };
+                    // Stored as white point, red, green, blue
+                    color_chunks.chromaticities = Some([
+                        (value(2), value(3)),
+                        (value(4), value(5)),
+                        (value(6), value(7)),
+                        (value(0), value(1)),
+                    ]);
+                }
+                _ => {}
+            }
+        }
+
+        color_chunks
+    }
+
+    /// ICC profile if the chunks describe something other than sRGB
+    ///
+    /// `sRGB` and `iCCP` chunks take precedence over `gAMA` and `cHRM`.
+    pub fn icc_profile(&self, gray: bool) -> Option<Vec<u8>> {
+        if self.srgb || self.iccp {
+            return None;
+        }
+
+        let gamma = self.gamma?;
+
+        let srgb_chromaticities = match self.chromaticities {
+            None => true,
+            Some(chromaticities) => {
+                let srgb = ColorPrimaries::Srgb.chromaticities().unwrap();
+                chromaticities
+                    .iter()
+                    .zip(srgb)
+                    .all(|(a, b)| (a.0 - b.0).abs() < 0.001 && (a.1 - b.1).abs() < 0.001)
+            }
+        };
+
+        if gamma == GAMMA_SRGB && (gray || srgb_chromaticities) {
+            return None;
+        }
+
+        gamma_icc_profile(100_000. / f64::from(gamma), self.chromaticities, gray)
+    }
+
+    pub fn rendering_intent(&self) -> Option<RenderingIntent> {
+        self.rendering_intent
+    }
+}
+
+/// Textual information from `tEXt`, `zTXt`, and `iTXt` chunks
+#[derive(Debug, PartialEq)]
+pub struct TextChunk {
+    pub keyword: String,
+    /// Language tag, only set for `iTXt`
+    pub language: String,
+    pub text: String,
+}
+
+/// Reads the text chunks with keywords accepted by `filter`
+///
+/// At most [`MAX_TEXT_CHUNKS`] chunks are read. Once the texts reach
+/// [`MAX_TOTAL_TEXT_SIZE`], no further chunks are decompressed.
+pub fn text_chunks<'a>(
+    data: &'a [u8],
+    filter: impl Fn(&str) -> bool + 'a,
+) -> impl Iterator<Item = TextChunk> + 'a {
+    chunks(data)
+        .filter(|(chunk_type, _)| matches!(chunk_type, b"tEXt" | b"zTXt" | b"iTXt"))
+        .filter_map(|(chunk_type, chunk_data)| {
+            let mut fields = chunk_data.splitn(2, |x| *x == 0);
+            let keyword = latin1(fields.next()?);
+            Some((chunk_type, keyword, fields.next()?))
+        })
+        .filter(move |(_, keyword, _)| filter(keyword))
+        .take(MAX_TEXT_CHUNKS)
+        .scan(
+            MAX_TOTAL_TEXT_SIZE,
+            |budget, (chunk_type, keyword, rest)| {
+                if *budget == 0 {
+                    return None;
+                }
+
+                let chunk = text_chunk(chunk_type, keyword, rest, MAX_TEXT_SIZE.min(*budget));
+                if let Some(chunk) = &chunk {
+                    let size = chunk.keyword.len() + chunk.text.len();
+                    *budget = budget.saturating_sub(size.try_into().unwrap_or(u64::MAX));
+                }
+
+                Some(chunk)
+            },
+        )
+        .flatten()
+}
+
+/// Text chunk from the data following the keyword, decompressed up to `limit`
+fn text_chunk(chunk_type: [u8; 4], keyword: String, rest: &[u8], limit: u64) -> Option<TextChunk> {
+    match &chunk_type {
+        b"tEXt" => Some(TextChunk {
+            keyword,
+            language: String::new(),
+            text: latin1(rest),
+        }),
+        b"zTXt" => {
+            let [0, compressed @ ..] = rest else {
+                return None;
+            };
+
+            Some(TextChunk {
+                keyword,
+                language: String::new(),
+                text: latin1(&inflate(compressed, limit)?),
+            })
+        }
+        b"iTXt" => {
+            let [compression_flag, 0, rest @ ..] = rest else {
+                return None;
+            };
+            let mut fields = rest.splitn(3, |x| *x == 0);
+            let language = latin1(fields.next()?);
+            let _translated_keyword = fields.next()?;
+            let text = match compression_flag {
+                0 => fields.next()?.to_vec(),
+                _ => inflate(fields.next()?, limit)?,
+            };
+
+            Some(TextChunk {
+                keyword,
+                language,
+                text: String::from_utf8_lossy(&text).to_string(),
+            })
+        }
+        _ => None,
+    }
+}
+
+fn latin1(data: &[u8]) -> String {
+    data.iter().map(|x| char::from(*x)).collect()
+}
+
+/// Decompresses zlib data up to `limit` bytes
+fn inflate(data: &[u8], limit: u64) -> Option<Vec<u8>> {
+    let mut inflated = Vec::new();
+    flate2::read::ZlibDecoder::new(data)
+        .take(limit)
+        .read_to_end(&mut inflated)
+        .ok()?;
+
+    Some(inflated)
+}
+
+/// Localized value of the text chunks with the given keyword
+pub fn localized_text(
+    text_chunks: &[TextChunk],
+    keyword: &str,
+    languages: &[String],
+) -> Option<String> {
+    let alternatives = text_chunks
+        .iter()
+        .filter(|x| x.keyword == keyword)
+        .map(|x| (x.language.as_str(), x.text.as_str()));
+
+    glycin_utils::localized_text(alternatives, languages).map(ToString::to_string)
+}
+
+/// Iterates over the types and data of all chunks
+pub fn chunks(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut pos = if data.starts_with(&SIGNATURE) {
+        SIGNATURE.len()
+    } else {
+        data.len()
+    };
+
+    std::iter::from_fn(move || {
+        let len = u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?);
+        let chunk_type = data.get(pos + 4..pos + 8)?.try_into().ok()?;
+        let chunk_data = data.get(pos + 8..(pos + 8).checked_add(len.try_into().ok()?)?)?;
+        // Data is followed by the CRC
+        pos += chunk_data.len() + 12;
+
+        Some((chunk_type, chunk_data))
+    })
+}
+
+#[test]
+fn text_chunks_test() {
+    let mut data = SIGNATURE.to_vec();
+    for (chunk_type, chunk_data) in [
+        (b"tEXt", &b"Title\0Caf\xE9"[..]),
+        (b"iTXt", &b"Title\0\0\0de\0Titel\0Caf\xC3\xA9 DE"[..]),
+        (
+            b"zTXt",
+            &b"Comment\0\0\x78\x9C\xF3\x48\xCD\xC9\xC9\x07\x00\x05\x8C\x01\xF5"[..],
+        ),
+    ] {
+        data.extend(u32::try_from(chunk_data.len()).unwrap().to_be_bytes());
+        data.extend(chunk_type);
+        data.extend(chunk_data);
+        data.extend([0; 4]);
+    }
+
+    let text_chunks: Vec<_> = text_chunks(&data, |_| true).collect();
+    assert_eq!(text_chunks[0].text, "Caf\u{E9}");
+    assert_eq!(text_chunks[2].text, "Hello");
+    assert_eq!(
+        localized_text(&text_chunks, "Title", &[String::from("de_DE")]).as_deref(),
+        Some("Caf\u{E9} DE")
+    );
+    assert_eq!(self::text_chunks(&data, |x| x == "Comment").count(), 1);
+}
+
+#[test]
+fn color_chunks_test() {
+    let mut data = SIGNATURE.to_vec();
+    data.extend(4_u32.to_be_bytes());
+    data.extend(b"gAMA");
+    data.extend(100_000_u32.to_be_bytes());
+    data.extend([0; 4]);
+    data.extend(0_u32.to_be_bytes());
+    data.extend(b"IEND");
+    data.extend([0; 4]);
+
+    let chunks = ColorChunks::parse(&data);
+    assert_eq!(chunks.gamma, Some(100_000));
+    assert!(chunks.icc_profile(false).is_some());
+
+    let mut data = SIGNATURE.to_vec();
+    data.extend(1_u32.to_be_bytes());
+    data.extend(b"sRGB");
+    data.extend([1]);
+    data.extend([0; 4]);
+
+    let chunks = ColorChunks::parse(&data);
+    assert!(chunks.icc_profile(false).is_none());
+    assert_eq!(
+        chunks.rendering_intent(),
+        Some(RenderingIntent::RelativeColorimetric)
+    );
+}
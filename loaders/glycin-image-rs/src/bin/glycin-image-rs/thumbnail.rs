@@ -0,0 +1,125 @@
+//! Embedded JPEG thumbnails and previews
+//!
+//! Searches the IFDs of the EXIF data, which is the whole file for TIFF-based
+//! formats. This covers the EXIF thumbnail in IFD1 as well as previews in
+//! further IFDs or SubIFDs, like in many RAW formats.
+
+use crate::ifd::Tiff;
+
+const TAG_COMPRESSION: u16 = 259;
+const TAG_STRIP_OFFSETS: u16 = 273;
+const TAG_STRIP_BYTE_COUNTS: u16 = 279;
+const TAG_SUB_IFDS: u16 = 330;
+const TAG_JPEG_INTERCHANGE_FORMAT: u16 = 513;
+const TAG_JPEG_INTERCHANGE_FORMAT_LENGTH: u16 = 514;
+
+/// Limit for IFDs to visit in malformed files
+const MAX_IFDS: usize = 64;
+
+pub struct Thumbnail {
+    /// JPEG data
+    pub data: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Finds the largest JPEG thumbnail that is smaller than the image itself
+pub fn thumbnail(exif: &[u8], image_width: u32, image_height: u32) -> Option<Thumbnail> {
+    let tiff = Tiff::new(exif)?;
+    let image_pixels = u64::from(image_width) * u64::from(image_height);
+
+    let mut ifds = vec![tiff.u32(4)?];
+    let mut visited = Vec::new();
+    let mut largest: Option<Thumbnail> = None;
+
+    while let Some(ifd) = ifds.pop() {
+        if ifd == 0 || visited.contains(&ifd) || visited.len() >= MAX_IFDS {
+            continue;
+        }
+        visited.push(ifd);
+
+        let Some((entries, next_ifd)) = tiff.ifd(ifd) else {
+            continue;
+        };
+        ifds.push(next_ifd);
+
+        let values = |tag| {
+            entries
+                .iter()
+                .find(|entry| entry.tag == tag)
+                .map(|entry| tiff.values(entry))
+                .unwrap_or_default()
+        };
+        ifds.extend(values(TAG_SUB_IFDS));
+
+        let jpeg_range = match (
+            values(TAG_JPEG_INTERCHANGE_FORMAT).as_slice(),
+            values(TAG_JPEG_INTERCHANGE_FORMAT_LENGTH).as_slice(),
+        ) {
+            ([offset], [len]) => Some((*offset, *len)),
+            // Old-style and new-style JPEG compression in a single strip
+            _ if matches!(values(TAG_COMPRESSION).as_slice(), [6 | 7]) => {
+                match (
+                    values(TAG_STRIP_OFFSETS).as_slice(),
+                    values(TAG_STRIP_BYTE_COUNTS).as_slice(),
+                ) {
+                    ([offset], [len]) => Some((*offset, *len)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+
+        let Some(data) = jpeg_range.and_then(|(offset, len)| tiff.bytes(offset, len)) else {
+            continue;
+        };
+        let Some((width, height)) = crate::jpeg::dimensions(data) else {
+            continue;
+        };
+
+        let pixels = u64::from(width) * u64::from(height);
+        // Previews as large as the image itself are not thumbnails
+        if pixels >= image_pixels {
+            continue;
+        }
+
+        if !largest
+            .as_ref()
+            .is_some_and(|x| pixels <= u64::from(x.width) * u64::from(x.height))
+        {
+            largest = Some(Thumbnail {
+                data: data.to_vec(),
+                width,
+                height,
+            });
+        }
+    }
+
+    largest
+}
+
+#[test]
+fn thumbnail_test() {
+    let jpeg = [
+        0xFF, 0xD8, 0xFF, 0xC0, 0, 11, 8, 0, 2, 0, 3, 1, 1, 0x11, 0, 0xFF, 0xDA, 0, 2,
+    ];
+
+    let mut exif = b"MM\0*".to_vec();
+    exif.extend(8_u32.to_be_bytes());
+    // IFD0 without entries, followed by IFD1
+    exif.extend(0_u16.to_be_bytes());
+    exif.extend(14_u32.to_be_bytes());
+    exif.extend(2_u16.to_be_bytes());
+    exif.extend(TAG_JPEG_INTERCHANGE_FORMAT.to_be_bytes());
+    exif.extend([0, 4, 0, 0, 0, 1]);
+    exif.extend(44_u32.to_be_bytes());
+    exif.extend(TAG_JPEG_INTERCHANGE_FORMAT_LENGTH.to_be_bytes());
+    exif.extend([0, 4, 0, 0, 0, 1]);
+    exif.extend(u32::try_from(jpeg.len()).unwrap().to_be_bytes());
+    exif.extend(0_u32.to_be_bytes());
+    exif.extend(jpeg);
+
+    let found = thumbnail(&exif, 300, 200).unwrap();
+    assert_eq!((found.width, found.height), (3, 2));
+    assert!(thumbnail(&exif, 3, 2).is_none());
+}
@@ -0,0 +1,92 @@
+//! XMP extraction
+//!
+//! image-rs doesn't expose XMP, so it's read from the file data directly.
+
+use std::io::Cursor;
+
+/// Namespace prefixing XMP in JPEG APP1 segments
+const JPEG_XMP_NAMESPACE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// Keyword of PNG `iTXt` chunks containing XMP
+const PNG_XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp\0";
+
+/// XMP TIFF tag
+const TIFF_TAG_XMP: u16 = 700;
+
+pub fn xmp(data: &[u8], mime_type: &str) -> Option<Vec<u8>> {
+    match mime_type {
+        "image/jpeg" => jpeg(data),
+        "image/png" => png(data),
+        "image/tiff" => tiff(data),
+        "image/webp" => webp(data),
+        _ => None,
+    }
+    .filter(|xmp| !xmp.is_empty())
+}
+
+fn jpeg(data: &[u8]) -> Option<Vec<u8>> {
+    crate::jpeg::segments(data)
+        // APP1
+        .filter(|(marker, _)| *marker == 0xE1)
+        .find_map(|(_, segment)| segment.strip_prefix(JPEG_XMP_NAMESPACE))
+        .map(<[u8]>::to_vec)
+}
+
+fn png(data: &[u8]) -> Option<Vec<u8>> {
+    let text = crate::png::chunks(data)
+        .filter(|(chunk_type, _)| chunk_type == b"iTXt")
+        .find_map(|(_, chunk_data)| chunk_data.strip_prefix(PNG_XMP_KEYWORD))?;
+
+    // Compressed XMP is not used in practice
+    let [0, _compression_method, text @ ..] = text else {
+        return None;
+    };
+
+    // Skip language tag and translated keyword
+    text.splitn(3, |x| *x == 0).nth(2).map(<[u8]>::to_vec)
+}
+
+fn tiff(data: &[u8]) -> Option<Vec<u8>> {
+    tiff::decoder::Decoder::new(Cursor::new(data))
+        .ok()?
+        .find_tag(tiff::tags::Tag::Unknown(TIFF_TAG_XMP))
+        .ok()??
+        .into_u8_vec()
+        .ok()
+}
+
+fn webp(data: &[u8]) -> Option<Vec<u8>> {
+    if data.get(..4)? != b"RIFF" || data.get(8..12)? != b"WEBP" {
+        return None;
+    }
+
+    let mut pos = 12;
+    while let Some(header) = data.get(pos..pos + 8) {
+        let (chunk_type, len) = header.split_at(4);
+        let len = usize::try_from(u32::from_le_bytes(len.try_into().ok()?)).ok()?;
+        let chunk_data = data.get(pos + 8..(pos + 8).checked_add(len)?)?;
+
+        if chunk_type == b"XMP " {
+            return Some(chunk_data.to_vec());
+        }
+
+        // Chunks are padded to an even size
+        pos += 8 + len + len % 2;
+    }
+
+    None
+}
+
+#[test]
+fn png_xmp_test() {
+    let mut chunk_data = PNG_XMP_KEYWORD.to_vec();
+    chunk_data.extend(b"\0\0\0\0<x:xmpmeta/>");
+
+    let mut data = crate::png::SIGNATURE.to_vec();
+    data.extend(u32::try_from(chunk_data.len()).unwrap().to_be_bytes());
+    data.extend(b"iTXt");
+    data.extend(chunk_data);
+    data.extend([0; 4]);
+
+    assert_eq!(png(&data).as_deref(), Some(&b"<x:xmpmeta/>"[..]));
+}
@@ -2,11 +2,12 @@
 
 use glycin_utils::*;
 
-use std::io::Cursor;
-use std::io::Read;
+use std::io::{Cursor, Read};
 use std::sync::Mutex;
 
-use jxl_oxide::{JxlImage, PixelFormat, RenderResult};
+use jxl_oxide::color::{ColourEncoding, Primaries, TransferFunction, WhitePoint};
+use jxl_oxide::image::BitDepth;
+use jxl_oxide::{ExtraChannel, FrameBuffer, JxlImage, PixelFormat, Render, RenderResult};
 
 fn main() {
     Communication::spawn(ImgDecoder::default());
@@ -14,57 +15,125 @@ fn main() {
 
 #[derive(Default)]
 pub struct ImgDecoder {
-    pub decoder: Mutex<Option<JxlImage<UnixStream>>>,
+    pub decoder: Mutex<Option<JxlImage<Cursor<Vec<u8>>>>>,
+    /// Orientation applied while rendering
+    pub orientation: Mutex<u32>,
 }
 
 impl Decoder for ImgDecoder {
     fn init(
         &self,
-        stream: UnixStream,
-        _details: DecodingDetails,
+        mut stream: UnixStream,
+        details: DecodingDetails,
     ) -> Result<ImageInfo, DecoderError> {
-        let image = JxlImage::from_reader(stream).unwrap();
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data).context_internal()?;
+
+        let xmp = container_box(&data, b"xml ").map(<[u8]>::to_vec);
+
+        let image = JxlImage::from_reader(Cursor::new(data)).map_err(decoding_error)?;
 
         let header = image.image_header();
 
-        let image_info = ImageInfo::new(
-            header.size.width,
-            header.size.height,
-            String::from("JPEG XL"),
-        );
+        let orientation = if details.apply_transformations {
+            header.metadata.orientation
+        } else {
+            1
+        };
 
-        *self.decoder.lock().unwrap() = Some(image);
+        let (width, height) = if orientation >= 5 {
+            (header.size.height, header.size.width)
+        } else {
+            (header.size.width, header.size.height)
+        };
+
+        let mut image_info = ImageInfo::new(width, height, String::from("JPEG XL"));
+        image_info.xmp = xmp.into();
+        image_info.transformations_applied = details.apply_transformations;
+
+        if !details.info_only {
+            *self.decoder.lock().unwrap() = Some(image);
+            *self.orientation.lock().unwrap() = orientation;
+        }
 
         Ok(image_info)
     }
 
-    fn decode_frame(&self, _frame_request: FrameRequest) -> Result<Frame, DecoderError> {
+    fn decode_frame(&self, frame_request: FrameRequest) -> Result<Frame, DecoderError> {
         let Some(mut image) = std::mem::take(&mut *self.decoder.lock().unwrap()) else {
             return Err(DecoderError::InternalDecoderError);
         };
 
+        let metadata = &image.image_header().metadata;
+        let cicp = cicp(&metadata.colour_encoding);
+        let hdr = cicp.is_some_and(|x| x.transfer().is_hdr());
+        let bit_depth = metadata.bit_depth;
         let mut renderer = image.renderer();
 
-        let RenderResult::Done(render) = renderer.render_next_frame().unwrap() else {
+        let RenderResult::Done(render) = renderer.render_next_frame().map_err(decoding_error)?
+        else {
             return Err(DecoderError::InternalDecoderError);
         };
 
-        let buffer = render.image();
+        let buffer = frame_buffer(&render, *self.orientation.lock().unwrap());
+        let n_channels = buffer.channels();
+
+        let Some(memory_format) = pixel_to_memory_format(renderer.pixel_format(), bit_depth, hdr)
+        else {
+            // Alpha is dropped, the black channel is the fourth channel
+            let cmyk = buffer
+                .buf()
+                .chunks_exact(n_channels)
+                .flat_map(|pixel| &pixel[..4])
+                // JPEG XL uses 0 for full ink
+                .map(|x| ((1. - x.clamp(0., 1.)) * u16::MAX as f32) as u16)
+                .collect();
+
+            return cmyk_frame(
+                buffer.width().try_u32()?,
+                buffer.height().try_u32()?,
+                CmykData::U16(cmyk),
+                Some(&renderer.rendered_icc()),
+                &frame_request.color_conversion,
+            );
+        };
 
-        // Buffer with channel size u16 = 2 bytes
-        let mut memory = SharedMemory::new(buffer.buf().len().try_u64()? * 2);
+        let mut memory = SharedMemory::new(
+            buffer.width().try_u64()? * buffer.height().try_u64()? * memory_format.n_bytes().u64(),
+        );
 
-        let u16_buffer: Vec<u16> = buffer
-            .buf()
-            .iter()
-            .map(|x| (x * u16::MAX as f32) as u16)
-            .collect();
+        match memory_format {
+            MemoryFormat::R32g32b32Float | MemoryFormat::R32g32b32a32Float => {
+                // Float values are kept as is, since HDR content can exceed 1.0
+                for (pixel, out_pixel) in buffer
+                    .buf()
+                    .chunks_exact(n_channels)
+                    .zip(memory.chunks_exact_mut(memory_format.n_bytes().usize()))
+                {
+                    for (value, out_value) in pixel.iter().zip(out_pixel.chunks_exact_mut(4)) {
+                        out_value.copy_from_slice(&value.to_ne_bytes());
+                    }
+                }
+            }
+            MemoryFormat::G8
+            | MemoryFormat::G8a8
+            | MemoryFormat::R8g8b8
+            | MemoryFormat::R8g8b8a8 => {
+                for (value, out_value) in buffer.buf().iter().zip(memory.iter_mut()) {
+                    *out_value = (value.clamp(0., 1.) * u8::MAX as f32).round() as u8;
+                }
+            }
+            _ => {
+                let u16_buffer: Vec<u16> = buffer
+                    .buf()
+                    .iter()
+                    .map(|x| (x.clamp(0., 1.) * u16::MAX as f32).round() as u16)
+                    .collect();
 
-        Cursor::new(safe_transmute::transmute_to_bytes(&u16_buffer))
-            .read_exact(&mut memory)
-            .unwrap();
+                memory.copy_from_slice(safe_transmute::transmute_to_bytes(&u16_buffer));
+            }
+        }
         let texture = memory.into_texture();
-        let memory_format = pixel_to_memory_format(renderer.pixel_format());
 
         let mut frame = Frame::new(
             buffer.width().try_u32()?,
@@ -72,19 +141,127 @@ impl Decoder for ImgDecoder {
             memory_format,
             texture,
         );
-        frame.iccp = Some(renderer.rendered_icc()).into();
+        frame.cicp = cicp.into();
+        // HDR values are only described by CICP, since an ICC transform would clip them
+        if !hdr {
+            frame.iccp = Some(renderer.rendered_icc()).into();
+        }
 
         Ok(frame)
     }
 }
 
-fn pixel_to_memory_format(format: PixelFormat) -> MemoryFormat {
+/// jxl-oxide returns boxed errors, which can't be used with `GenericContexts`
+fn decoding_error(err: Box<dyn std::error::Error + Send + Sync>) -> DecoderError {
+    DecoderError::DecodingError(format!("{err}"))
+}
+
+/// Interleaved channels with the given orientation applied
+///
+/// `Render::image()` always applies the orientation from the header.
+fn frame_buffer(render: &Render, orientation: u32) -> FrameBuffer {
+    let mut grids = render.color_channels().to_vec();
+    // Black is followed by alpha, like in `Render::image()`
+    for is_channel in [ExtraChannel::is_black, ExtraChannel::is_alpha] {
+        if let Some(channel) = render.extra_channels().iter().find(|x| is_channel(x)) {
+            grids.push(channel.grid().clone());
+        }
+    }
+
+    FrameBuffer::from_grids(&grids, orientation)
+}
+
+/// Content of the first box of the given type in the JPEG XL container
+///
+/// Bare codestreams without container don't have boxes.
+fn container_box<'a>(data: &'a [u8], box_type: &[u8; 4]) -> Option<&'a [u8]> {
+    const SIGNATURE: &[u8] = b"\0\0\0\x0CJXL \r\n\x87\n";
+
+    if !data.starts_with(SIGNATURE) {
+        return None;
+    }
+
+    let mut pos = 0;
+    while let Some(header) = data.get(pos..pos + 8) {
+        let (size, current_type) = header.split_at(4);
+        let (header_len, size) = match u32::from_be_bytes(size.try_into().ok()?) {
+            // Box extends to the end of the file
+            0 => (8, data.len() - pos),
+            // 64 bit size follows the type
+            1 => {
+                let size = u64::from_be_bytes(data.get(pos + 8..pos + 16)?.try_into().ok()?);
+                (16, usize::try_from(size).ok()?)
+            }
+            size => (8, usize::try_from(size).ok()?),
+        };
+
+        let end = pos.checked_add(size)?;
+        let content = data.get(pos.checked_add(header_len)?..end)?;
+
+        if current_type == box_type {
+            return Some(content);
+        }
+
+        pos = end;
+    }
+
+    None
+}
+
+/// CICP for colour encodings that are not given as ICC profile
+fn cicp(encoding: &ColourEncoding) -> Option<Cicp> {
+    if encoding.want_icc {
+        return None;
+    }
+
+    let color_primaries = match (encoding.primaries, encoding.white_point) {
+        (Primaries::Srgb, WhitePoint::D65) => ColorPrimaries::Srgb,
+        (Primaries::Bt2100, WhitePoint::D65) => ColorPrimaries::Bt2020,
+        (Primaries::P3, WhitePoint::D65) => ColorPrimaries::DisplayP3,
+        (Primaries::P3, WhitePoint::Dci) => ColorPrimaries::DciP3,
+        _ => ColorPrimaries::Unspecified,
+    };
+
+    let transfer_characteristics = match encoding.tf {
+        TransferFunction::Bt709 => TransferCharacteristics::Bt709,
+        TransferFunction::Linear => TransferCharacteristics::Linear,
+        TransferFunction::Srgb => TransferCharacteristics::Srgb,
+        TransferFunction::Pq => TransferCharacteristics::Pq,
+        TransferFunction::Hlg => TransferCharacteristics::Hlg,
+        // Stored as inverse gamma times 10^7
+        TransferFunction::Gamma(4_545_455) => TransferCharacteristics::Gamma22,
+        TransferFunction::Gamma(3_571_429) => TransferCharacteristics::Gamma28,
+        _ => TransferCharacteristics::Unspecified,
+    };
+
+    Some(Cicp::new(color_primaries, transfer_characteristics))
+}
+
+/// Float is only used for HDR or float samples, otherwise 8 or 16 bit
+///
+/// There are no grayscale float formats, so grayscale is clamped to 16 bit.
+/// CMYK has no memory format and is converted to RGB instead.
+fn pixel_to_memory_format(
+    format: PixelFormat,
+    bit_depth: BitDepth,
+    hdr: bool,
+) -> Option<MemoryFormat> {
+    let (float, eight_bit) = match bit_depth {
+        BitDepth::FloatSample { .. } => (true, false),
+        BitDepth::IntegerSample { bits_per_sample } => (hdr, bits_per_sample <= 8),
+    };
+
     match format {
-        PixelFormat::Gray => MemoryFormat::G16,
-        PixelFormat::Graya => MemoryFormat::G16a16,
-        PixelFormat::Rgb => MemoryFormat::R16g16b16,
-        PixelFormat::Rgba => MemoryFormat::R16g16b16a16,
-        PixelFormat::Cmyk => MemoryFormat::R16g16b16,
-        PixelFormat::Cmyka => MemoryFormat::R16g16b16a16,
+        PixelFormat::Gray if eight_bit => Some(MemoryFormat::G8),
+        PixelFormat::Gray => Some(MemoryFormat::G16),
+        PixelFormat::Graya if eight_bit => Some(MemoryFormat::G8a8),
+        PixelFormat::Graya => Some(MemoryFormat::G16a16),
+        PixelFormat::Rgb if float => Some(MemoryFormat::R32g32b32Float),
+        PixelFormat::Rgb if eight_bit => Some(MemoryFormat::R8g8b8),
+        PixelFormat::Rgb => Some(MemoryFormat::R16g16b16),
+        PixelFormat::Rgba if float => Some(MemoryFormat::R32g32b32a32Float),
+        PixelFormat::Rgba if eight_bit => Some(MemoryFormat::R8g8b8a8),
+        PixelFormat::Rgba => Some(MemoryFormat::R16g16b16a16),
+        PixelFormat::Cmyk | PixelFormat::Cmyka => None,
     }
 }
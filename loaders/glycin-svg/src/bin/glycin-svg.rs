@@ -1,3 +1,4 @@
+use gio::glib;
 use gio::prelude::*;
 use glycin_utils::anyhow::Context;
 use glycin_utils::*;
@@ -5,6 +6,9 @@ use std::io::Cursor;
 use std::io::Read;
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::Mutex;
+use xml5ever::buffer_queue::BufferQueue;
+use xml5ever::tendril::StrTendril;
+use xml5ever::tokenizer::{TagKind, Token, TokenSink, XmlTokenizer, XmlTokenizerOpts};
 
 /// Current librsvg limit on maximum dimensions. See
 /// <https://gitlab.gnome.org/GNOME/librsvg/-/issues/938>
@@ -31,13 +35,27 @@ pub struct Instruction {
 }
 
 pub fn thread(
-    stream: UnixStream,
+    mut stream: UnixStream,
     base_file: Option<gio::File>,
+    languages: Vec<String>,
     info_send: Sender<Result<ImageInfo, DecoderError>>,
     frame_send: Sender<Result<Frame, DecoderError>>,
     instr_recv: Receiver<Instruction>,
 ) {
-    let input_stream = unsafe { gio::UnixInputStream::take_fd(stream) };
+    let mut data = Vec::new();
+    if let Err(err) = stream.read_to_end(&mut data) {
+        info_send.send(Err(err).context_internal()).unwrap();
+        return;
+    }
+    // Read SVGZ only once for librsvg and for reading the text elements
+    let data = match decompress(data) {
+        Ok(data) => data,
+        Err(err) => {
+            info_send.send(Err(err)).unwrap();
+            return;
+        }
+    };
+    let input_stream = gio::MemoryInputStream::from_bytes(&glib::Bytes::from(&data));
 
     let handle = rsvg::Loader::new()
         .read_stream(&input_stream, base_file.as_ref(), gio::Cancellable::NONE)
@@ -51,6 +69,17 @@ pub fn thread(
     image_info.dimensions_text = dimensions_text(renderer.intrinsic_dimensions()).into();
     image_info.dimensions_inch = dimensions_inch(renderer.intrinsic_dimensions()).into();
 
+    let text = root_text_elements(&data);
+    let localized = |name: &str| {
+        let alternatives = text
+            .iter()
+            .filter(|(element, _, _)| element == name)
+            .map(|(_, lang, text)| (lang.as_str(), text.as_str()));
+        localized_text(alternatives, &languages).map(ToString::to_string)
+    };
+    image_info.title = localized("title").into();
+    image_info.description = localized("desc").into();
+
     info_send.send(Ok(image_info)).unwrap();
 
     while let Ok(instr) = instr_recv.recv() {
@@ -67,6 +96,25 @@ pub fn thread(
     }
 }
 
+/// Decompresses gzip compressed data (SVGZ)
+pub fn decompress(data: Vec<u8>) -> Result<Vec<u8>, DecoderError> {
+    if !data.starts_with(&[0x1F, 0x8B]) {
+        return Ok(data);
+    }
+
+    let converter = gio::ZlibDecompressor::new(gio::ZlibCompressorFormat::Gzip);
+    let input_stream = gio::MemoryInputStream::from_bytes(&glib::Bytes::from_owned(data));
+    let converter_stream = gio::ConverterInputStream::new(&input_stream, &converter);
+
+    let mut data = Vec::new();
+    converter_stream
+        .into_read()
+        .read_to_end(&mut data)
+        .context_failed()?;
+
+    Ok(data)
+}
+
 pub fn render(renderer: &rsvg::CairoRenderer, instr: Instruction) -> Result<Frame, DecoderError> {
     let area = instr.area;
     let (total_width, total_height) = instr.total_size;
@@ -135,9 +183,20 @@ impl Decoder for ImgDecoder {
             .as_ref()
             .map(|x| gio::File::for_path(x).child("placeholder.svg"));
 
-        std::thread::spawn(move || thread(stream, base_file, info_send, frame_send, instr_recv));
+        let languages = details.languages.clone();
+
+        std::thread::spawn(move || {
+            thread(
+                stream, base_file, languages, info_send, frame_send, instr_recv,
+            )
+        });
         let image_info = info_recv.recv().unwrap()?;
 
+        // Dropping the instruction sender ends the render thread
+        if details.info_only {
+            return Ok(image_info);
+        }
+
         *self.thread.lock().unwrap() = Some(ImgDecoderDetails {
             frame_recv,
             instr_send,
@@ -264,3 +323,67 @@ pub fn dimension_inch(length: rsvg::Length) -> Option<f64> {
         _ => None,
     }
 }
+
+/// Name, language, and text of `title` and `desc` elements of the root element
+///
+/// These provide the accessible name and description of the document.
+pub fn root_text_elements(svg: &[u8]) -> Vec<(String, String, String)> {
+    let mut input = BufferQueue::new();
+    input.push_back(StrTendril::from(String::from_utf8_lossy(svg).as_ref()));
+
+    let mut tokenizer = XmlTokenizer::new(TextElements::default(), XmlTokenizerOpts::default());
+    tokenizer.feed(&mut input);
+    tokenizer.end();
+
+    tokenizer.sink.elements
+}
+
+/// Collects the `title` and `desc` elements of the root element
+#[derive(Default)]
+struct TextElements {
+    depth: usize,
+    /// Element that is currently read
+    current: Option<(String, String, String)>,
+    elements: Vec<(String, String, String)>,
+}
+
+impl TokenSink for TextElements {
+    fn process_token(&mut self, token: Token) {
+        match token {
+            Token::TagToken(tag) => match tag.kind {
+                TagKind::StartTag => {
+                    let name = tag.name.local.as_ref();
+                    if self.depth == 1 && matches!(name, "title" | "desc") {
+                        // `xml:lang` takes precedence over `lang`
+                        let lang = tag
+                            .attrs
+                            .iter()
+                            .filter(|x| x.name.local.as_ref() == "lang")
+                            .min_by_key(|x| x.name.prefix.as_deref() != Some("xml"))
+                            .map(|x| x.value.to_string())
+                            .unwrap_or_default();
+                        self.current = Some((name.to_string(), lang, String::new()));
+                    }
+                    self.depth += 1;
+                }
+                TagKind::EndTag | TagKind::ShortTag => {
+                    self.depth = self.depth.saturating_sub(1);
+                    if self.depth == 1 {
+                        if let Some((name, lang, text)) = self.current.take() {
+                            // Whitespace is collapsed
+                            let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                            self.elements.push((name, lang, text));
+                        }
+                    }
+                }
+                TagKind::EmptyTag => {}
+            },
+            Token::CharacterTokens(text) => {
+                if let Some((_, _, current_text)) = &mut self.current {
+                    current_text.push_str(&text);
+                }
+            }
+            _ => {}
+        }
+    }
+}
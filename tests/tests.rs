@@ -27,6 +27,47 @@ fn exif() {
     async_std::task::block_on(test_dir("test-images/images/exif"));
 }
 
+#[test]
+fn info_only() {
+    async_std::task::block_on(async {
+        let paths: Vec<_> = std::fs::read_dir("test-images/images/exif")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+
+        let requests = paths
+            .iter()
+            .map(|path| glycin::ImageRequest::new(gio::File::for_path(path)));
+        let infos = glycin::ImageRequest::request_infos(requests).await;
+
+        for (path, info) in paths.iter().zip(infos) {
+            let info = info.unwrap();
+            let reference_info = get_info(path).await;
+            assert_eq!(
+                (info.width, info.height, info.exif_info),
+                (
+                    reference_info.width,
+                    reference_info.height,
+                    reference_info.exif_info
+                ),
+                "{path:?}"
+            );
+        }
+    });
+}
+
+#[test]
+fn exif_info() {
+    async_std::task::block_on(async {
+        let info = get_info("images/exif.png").await;
+        let exif_info = info.exif_info.as_ref().unwrap();
+        assert_eq!(exif_info.make.as_deref(), Some("Canon"));
+        assert_eq!(exif_info.model.as_deref(), Some("GX1"));
+        assert_eq!(exif_info.orientation.as_ref(), Some(&1));
+        assert_eq!(exif_info.iso.as_ref(), None);
+    });
+}
+
 #[allow(dead_code)]
 #[derive(Debug)]
 struct TestResult {